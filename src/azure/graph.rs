@@ -1,45 +1,604 @@
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 
-use crate::azure::LazyToken;
+use crate::azure::{json_or_error, ok_or_error, Activation, LazyToken, PolicyRule, ReviewResult};
 
-const MS_GRAPH_URL: &'static str = "https://graph.microsoft.com";
+pub const MS_GRAPH_URL: &'static str = "https://graph.microsoft.com";
+
+/// Delegated permissions needed to read and request roles, which the Azure CLI does not have by default
+const PERMISSIONS: [&str; 7] = [
+    "User.Read",
+    "RoleEligibilitySchedule.ReadWrite.Directory",
+    "RoleAssignmentSchedule.ReadWrite.Directory",
+    "RoleManagementPolicy.Read.Directory",
+    "PrivilegedEligibilitySchedule.ReadWrite.AzureADGroup",
+    "PrivilegedAssignmentSchedule.ReadWrite.AzureADGroup",
+    "RoleManagementPolicy.Read.AzureADGroup",
+];
 
 pub struct GraphClient {
     client: Client,
     token: LazyToken,
+    // Base URL of Microsoft Graph, also the resource tokens are requested for
+    url: String,
 }
 
 impl GraphClient {
-    pub fn new() -> Self {
+    pub fn new(url: &str) -> Self {
+        let url = url.trim_end_matches('/');
         GraphClient {
             client: Client::new(),
-            token: LazyToken::new(MS_GRAPH_URL),
+            token: LazyToken::with_scopes(url, PERMISSIONS.iter().map(|permission| format!("{}/{}", url, permission)).collect()),
+            url: url.to_owned(),
         }
     }
 
-    pub async fn get_groups(&self, filter: &str) -> Vec<GraphGroup> {
-        self.client.get(format!("{}/v1.0/groups?{}", MS_GRAPH_URL, filter))
+    pub async fn get_eligible_groups(&self) -> Result<Vec<GroupInstance>, String> {
+        self.get_group_instances("eligibilityScheduleInstances").await
+    }
+
+    pub async fn get_active_groups(&self) -> Result<Vec<GroupInstance>, String> {
+        self.get_group_instances("assignmentScheduleInstances").await
+    }
+
+    async fn get_group_instances(&self, instances: &str) -> Result<Vec<GroupInstance>, String> {
+        let token = self.token.token().await;
+        let response = self.client.get(format!(
+            "{}/v1.0/identityGovernance/privilegedAccess/group/{}?$filter=principalId eq '{}'&$expand=group",
+            self.url,
+            instances,
+            token.subject_id
+        ))
+            .header("Authorization", format!("Bearer {}", token))
+            .send()
+            .await
+            .map_err(|error| error.to_string())?;
+        json_or_error::<GroupInstances>(response).await.map(|instances| instances.value)
+    }
+
+    pub async fn request_group(&self, activation: Activation, group_id: String, access_id: String) -> Result<ScheduleRequest, String> {
+        let token = self.token.token().await;
+        self.submit_request(
+            format!("{}/v1.0/identityGovernance/privilegedAccess/group/assignmentScheduleRequests", self.url),
+            ScheduleRequestBody {
+                group_id: Some(group_id),
+                access_id: Some(access_id),
+                ..ScheduleRequestBody::activation(token.subject_id.to_owned(), activation)
+            },
+        ).await
+    }
+
+    pub async fn request_group_renewal(&self, activation: Activation, group_id: String, access_id: String, expired: bool) -> Result<ScheduleRequest, String> {
+        let token = self.token.token().await;
+        self.submit_request(
+            format!("{}/v1.0/identityGovernance/privilegedAccess/group/eligibilityScheduleRequests", self.url),
+            ScheduleRequestBody {
+                group_id: Some(group_id),
+                access_id: Some(access_id),
+                ..ScheduleRequestBody::renewal(token.subject_id.to_owned(), activation, expired)
+            },
+        ).await
+    }
+
+    pub async fn request_group_deactivation(&self, group_id: String, access_id: String) -> Result<ScheduleRequest, String> {
+        let token = self.token.token().await;
+        self.submit_request(
+            format!("{}/v1.0/identityGovernance/privilegedAccess/group/assignmentScheduleRequests", self.url),
+            ScheduleRequestBody {
+                group_id: Some(group_id),
+                access_id: Some(access_id),
+                ..ScheduleRequestBody::deactivation(token.subject_id.to_owned())
+            },
+        ).await
+    }
+
+    pub async fn get_group_request(&self, request_id: &str) -> Result<ScheduleRequest, String> {
+        self.get_request(format!("{}/v1.0/identityGovernance/privilegedAccess/group/assignmentScheduleRequests/{}", self.url, request_id)).await
+    }
+
+    /// Requests the signed-in user has made for a group
+    pub async fn get_group_requests(&self, group_id: &str, access_id: &str) -> Result<Vec<ScheduleRequest>, String> {
+        let token = self.token.token().await;
+        self.get_schedule_requests(format!(
+            "{}/v1.0/identityGovernance/privilegedAccess/group/assignmentScheduleRequests?$filter=principalId eq '{}' and groupId eq '{}' and accessId eq '{}'",
+            self.url,
+            token.subject_id,
+            group_id,
+            access_id
+        )).await
+    }
+
+    /// Requests the signed-in user has made for groups, with the group they were made for
+    pub async fn get_my_group_requests(&self) -> Result<Vec<ScheduleRequest>, String> {
+        let token = self.token.token().await;
+        self.get_schedule_requests(format!(
+            "{}/v1.0/identityGovernance/privilegedAccess/group/assignmentScheduleRequests?$filter=principalId eq '{}'&$expand=group",
+            self.url,
+            token.subject_id
+        )).await
+    }
+
+    pub async fn cancel_group_request(&self, request_id: &str) -> Result<(), String> {
+        self.cancel_request(format!("{}/v1.0/identityGovernance/privilegedAccess/group/assignmentScheduleRequests/{}/cancel", self.url, request_id)).await
+    }
+
+    /// Display name of the signed-in user's tenant, if it could be read
+    pub async fn get_organization_name(&self) -> Option<String> {
+        let response = self.client.get(format!("{}/v1.0/organization?$select=displayName", self.url))
             .header("Authorization", format!("Bearer {}", self.token.token().await))
             .send()
             .await
-            .unwrap()
-            .json::<GraphGroups>()
+            .ok()?;
+        json_or_error::<Organizations>(response).await
+            .ok()?
+            .value
+            .into_iter()
+            .next()
+            .map(|organization| organization.display_name)
+    }
+
+    pub async fn get_eligible_directory_roles(&self) -> Result<Vec<DirectoryRoleInstance>, String> {
+        self.get_directory_role_instances("roleEligibilityScheduleInstances").await
+    }
+
+    pub async fn get_active_directory_roles(&self) -> Result<Vec<DirectoryRoleInstance>, String> {
+        self.get_directory_role_instances("roleAssignmentScheduleInstances").await
+    }
+
+    async fn get_directory_role_instances(&self, instances: &str) -> Result<Vec<DirectoryRoleInstance>, String> {
+        let token = self.token.token().await;
+        let response = self.client.get(format!(
+            "{}/v1.0/roleManagement/directory/{}?$filter=principalId eq '{}'&$expand=roleDefinition,principal,directoryScope",
+            self.url,
+            instances,
+            token.subject_id
+        ))
+            .header("Authorization", format!("Bearer {}", token))
+            .send()
             .await
-            .unwrap()
+            .map_err(|error| error.to_string())?;
+        json_or_error::<DirectoryRoleInstances>(response).await.map(|instances| instances.value)
+    }
+
+    pub async fn request_directory_role(
+        &self,
+        activation: Activation,
+        directory_scope_id: String,
+        role_definition_id: String,
+    ) -> Result<ScheduleRequest, String> {
+        let token = self.token.token().await;
+        self.submit_request(
+            format!("{}/v1.0/roleManagement/directory/roleAssignmentScheduleRequests", self.url),
+            ScheduleRequestBody {
+                role_definition_id: Some(role_definition_id),
+                directory_scope_id: Some(directory_scope_id),
+                ..ScheduleRequestBody::activation(token.subject_id.to_owned(), activation)
+            },
+        ).await
+    }
+
+    pub async fn request_directory_role_renewal(
+        &self,
+        activation: Activation,
+        directory_scope_id: String,
+        role_definition_id: String,
+        expired: bool,
+    ) -> Result<ScheduleRequest, String> {
+        let token = self.token.token().await;
+        self.submit_request(
+            format!("{}/v1.0/roleManagement/directory/roleEligibilityScheduleRequests", self.url),
+            ScheduleRequestBody {
+                role_definition_id: Some(role_definition_id),
+                directory_scope_id: Some(directory_scope_id),
+                ..ScheduleRequestBody::renewal(token.subject_id.to_owned(), activation, expired)
+            },
+        ).await
+    }
+
+    pub async fn request_directory_role_deactivation(
+        &self,
+        directory_scope_id: String,
+        role_definition_id: String,
+    ) -> Result<ScheduleRequest, String> {
+        let token = self.token.token().await;
+        self.submit_request(
+            format!("{}/v1.0/roleManagement/directory/roleAssignmentScheduleRequests", self.url),
+            ScheduleRequestBody {
+                role_definition_id: Some(role_definition_id),
+                directory_scope_id: Some(directory_scope_id),
+                ..ScheduleRequestBody::deactivation(token.subject_id.to_owned())
+            },
+        ).await
+    }
+
+    async fn submit_request(&self, url: String, body: ScheduleRequestBody) -> Result<ScheduleRequest, String> {
+        let response = self.client.post(url)
+            .header("Authorization", format!("Bearer {}", self.token.token().await))
+            .json(&body)
+            .send()
+            .await
+            .unwrap();
+        json_or_error(response).await
+    }
+
+    pub async fn get_directory_role_request(&self, request_id: &str) -> Result<ScheduleRequest, String> {
+        self.get_request(format!("{}/v1.0/roleManagement/directory/roleAssignmentScheduleRequests/{}", self.url, request_id)).await
+    }
+
+    async fn get_request(&self, url: String) -> Result<ScheduleRequest, String> {
+        let response = self.client.get(url)
+            .header("Authorization", format!("Bearer {}", self.token.token().await))
+            .send()
+            .await
+            .map_err(|error| error.to_string())?;
+        json_or_error(response).await
+    }
+
+    /// Requests the signed-in user has made for a directory role at a scope
+    pub async fn get_directory_role_requests(&self, directory_scope_id: &str, role_definition_id: &str) -> Result<Vec<ScheduleRequest>, String> {
+        let token = self.token.token().await;
+        self.get_schedule_requests(format!(
+            "{}/v1.0/roleManagement/directory/roleAssignmentScheduleRequests?$filter=principalId eq '{}' and directoryScopeId eq '{}' and roleDefinitionId eq '{}'",
+            self.url,
+            token.subject_id,
+            directory_scope_id,
+            role_definition_id
+        )).await
+    }
+
+    /// Requests the signed-in user has made for directory roles, with the role and scope they were made for
+    pub async fn get_my_directory_role_requests(&self) -> Result<Vec<ScheduleRequest>, String> {
+        let token = self.token.token().await;
+        self.get_schedule_requests(format!(
+            "{}/v1.0/roleManagement/directory/roleAssignmentScheduleRequests?$filter=principalId eq '{}'&$expand=roleDefinition,directoryScope",
+            self.url,
+            token.subject_id
+        )).await
+    }
+
+    pub async fn cancel_directory_role_request(&self, request_id: &str) -> Result<(), String> {
+        self.cancel_request(format!("{}/v1.0/roleManagement/directory/roleAssignmentScheduleRequests/{}/cancel", self.url, request_id)).await
+    }
+
+    async fn cancel_request(&self, url: String) -> Result<(), String> {
+        let response = self.client.post(url)
+            .header("Authorization", format!("Bearer {}", self.token.token().await))
+            .header("Content-Length", "0")
+            .send()
+            .await
+            .unwrap();
+        ok_or_error(response).await
+    }
+
+    pub async fn ensure_token(&self) {
+        self.token.token().await;
+    }
+
+    /// Rules of the role management policy for a role at a scope, empty if no policy is assigned
+    pub async fn get_policy_rules(&self, scope_id: &str, scope_type: &str, role_definition_id: &str) -> Result<Vec<PolicyRule>, String> {
+        let response = self.client.get(format!(
+            "{}/v1.0/policies/roleManagementPolicyAssignments?$filter=scopeId eq '{}' and scopeType eq '{}' and roleDefinitionId eq '{}'&$expand=policy($expand=rules)",
+            self.url,
+            scope_id,
+            scope_type,
+            role_definition_id
+        ))
+            .header("Authorization", format!("Bearer {}", self.token.token().await))
+            .send()
+            .await
+            .map_err(|error| error.to_string())?;
+        Ok(json_or_error::<PolicyAssignments>(response).await?
             .value
+            .into_iter()
+            .next()
+            .map(|assignment| assignment.policy.rules)
+            .unwrap_or_default())
+    }
+
+    pub async fn get_group_requests_to_approve(&self) -> Result<Vec<ScheduleRequest>, String> {
+        self.get_schedule_requests(format!(
+            "{}/v1.0/identityGovernance/privilegedAccess/group/assignmentScheduleRequests/filterByCurrentUser(on='approver')?$filter=status eq 'PendingApproval'&$expand=principal,group",
+            self.url
+        )).await
+    }
+
+    pub async fn get_directory_role_requests_to_approve(&self) -> Result<Vec<ScheduleRequest>, String> {
+        self.get_schedule_requests(format!(
+            "{}/v1.0/roleManagement/directory/roleAssignmentScheduleRequests/filterByCurrentUser(on='approver')?$filter=status eq 'PendingApproval'&$expand=principal,roleDefinition,directoryScope",
+            self.url
+        )).await
+    }
+
+    async fn get_schedule_requests(&self, url: String) -> Result<Vec<ScheduleRequest>, String> {
+        let response = self.client.get(url)
+            .header("Authorization", format!("Bearer {}", self.token.token().await))
+            .send()
+            .await
+            .map_err(|error| error.to_string())?;
+        json_or_error::<ScheduleRequests>(response).await.map(|requests| requests.value)
+    }
+
+    pub async fn review_group_request(&self, approval_id: &str, review_result: ReviewResult, justification: String) -> Result<(), String> {
+        self.review(
+            format!("{}/v1.0/identityGovernance/privilegedAccess/group/assignmentApprovals/{}", self.url, approval_id),
+            review_result,
+            justification,
+        ).await
+    }
+
+    pub async fn review_directory_role_request(&self, approval_id: &str, review_result: ReviewResult, justification: String) -> Result<(), String> {
+        self.review(
+            format!("{}/beta/roleManagement/directory/roleAssignmentApprovals/{}", self.url, approval_id),
+            review_result,
+            justification,
+        ).await
+    }
+
+    /// Reviews the step of an approval that is currently waiting for the signed-in user
+    async fn review(&self, approval_url: String, review_result: ReviewResult, justification: String) -> Result<(), String> {
+        let token = self.token.token().await;
+        let response = self.client.get(&approval_url)
+            .header("Authorization", format!("Bearer {}", token))
+            .send()
+            .await
+            .unwrap();
+        let approval = json_or_error::<Approval>(response).await?;
+        let step = approval.steps.into_iter()
+            .find(|step| step.status == "InProgress" && step.assigned_to_me.unwrap_or(false))
+            .ok_or_else(|| "No approval step is waiting for you".to_owned())?;
+        let response = self.client.patch(format!("{}/steps/{}", approval_url, step.id))
+            .header("Authorization", format!("Bearer {}", token))
+            .json(&ApprovalStepReview {
+                review_result,
+                justification,
+            })
+            .send()
+            .await
+            .unwrap();
+        ok_or_error(response).await
     }
 }
 
-#[derive(Serialize, Deserialize)]
-pub struct GraphGroup {
-    pub id: String,
+#[derive(Deserialize)]
+pub struct NamedObject {
+    #[serde(rename = "displayName")]
+    pub display_name: Option<String>,
     pub description: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Organization {
     #[serde(rename = "displayName")]
-    pub display_name: String,
+    display_name: String,
+}
+
+#[derive(Deserialize)]
+struct Organizations {
+    value: Vec<Organization>,
+}
+
+/// Eligible or active assignment of a directory role
+#[derive(Deserialize)]
+pub struct DirectoryRoleInstance {
+    pub id: String,
+    #[serde(rename = "roleDefinitionId")]
+    pub role_definition_id: String,
+    #[serde(rename = "directoryScopeId")]
+    pub directory_scope_id: String,
+    // Only set for active assignments, `Activated` or `Assigned`
+    #[serde(rename = "assignmentType")]
+    pub assignment_type: Option<String>,
+    #[serde(rename = "startDateTime")]
+    pub start_date_time: Option<String>,
+    #[serde(rename = "endDateTime")]
+    pub end_date_time: Option<String>,
+    #[serde(rename = "roleDefinition")]
+    pub role_definition: Option<NamedObject>,
+    pub principal: Option<NamedObject>,
+    // Not set for the tenant wide scope `/`
+    #[serde(rename = "directoryScope")]
+    pub directory_scope: Option<NamedObject>,
+}
+
+#[derive(Deserialize)]
+struct DirectoryRoleInstances {
+    value: Vec<DirectoryRoleInstance>,
+}
+
+/// Eligible or active membership or ownership of a group
+#[derive(Deserialize)]
+pub struct GroupInstance {
+    pub id: String,
+    #[serde(rename = "groupId")]
+    pub group_id: String,
+    // `member` or `owner`
+    #[serde(rename = "accessId")]
+    pub access_id: String,
+    // Only set for active assignments, `activated` or `assigned`
+    #[serde(rename = "assignmentType")]
+    pub assignment_type: Option<String>,
+    #[serde(rename = "startDateTime")]
+    pub start_date_time: Option<String>,
+    #[serde(rename = "endDateTime")]
+    pub end_date_time: Option<String>,
+    pub group: Option<NamedObject>,
+}
+
+#[derive(Deserialize)]
+struct GroupInstances {
+    value: Vec<GroupInstance>,
+}
+
+#[derive(Serialize)]
+struct RequestExpiration {
+    #[serde(rename = "type")]
+    expiration_type: String,
+    duration: String,
+}
+
+#[derive(Serialize)]
+struct RequestScheduleInfo {
+    #[serde(rename = "startDateTime", skip_serializing_if = "Option::is_none")]
+    start_date_time: Option<String>,
+    expiration: RequestExpiration,
+}
+
+#[derive(Serialize)]
+struct TicketInfo {
+    #[serde(rename = "ticketNumber")]
+    ticket_number: String,
+    #[serde(rename = "ticketSystem")]
+    ticket_system: String,
+}
+
+/// Request for a directory role or a group, only the ids of the requested target are set
+#[derive(Serialize)]
+struct ScheduleRequestBody {
+    action: String,
+    #[serde(rename = "principalId")]
+    principal_id: String,
+    #[serde(rename = "roleDefinitionId", skip_serializing_if = "Option::is_none")]
+    role_definition_id: Option<String>,
+    #[serde(rename = "directoryScopeId", skip_serializing_if = "Option::is_none")]
+    directory_scope_id: Option<String>,
+    #[serde(rename = "groupId", skip_serializing_if = "Option::is_none")]
+    group_id: Option<String>,
+    #[serde(rename = "accessId", skip_serializing_if = "Option::is_none")]
+    access_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    justification: Option<String>,
+    #[serde(rename = "scheduleInfo", skip_serializing_if = "Option::is_none")]
+    schedule_info: Option<RequestScheduleInfo>,
+    #[serde(rename = "ticketInfo", skip_serializing_if = "Option::is_none")]
+    ticket_info: Option<TicketInfo>,
+}
+
+impl ScheduleRequestBody {
+    fn activation(principal_id: String, activation: Activation) -> ScheduleRequestBody {
+        ScheduleRequestBody::scheduled("selfActivate", principal_id, activation)
+    }
+
+    /// Extends an eligibility nearing its end, or renews one that has already expired
+    fn renewal(principal_id: String, activation: Activation, expired: bool) -> ScheduleRequestBody {
+        ScheduleRequestBody::scheduled(if expired { "selfRenew" } else { "selfExtend" }, principal_id, activation)
+    }
+
+    fn scheduled(action: &str, principal_id: String, activation: Activation) -> ScheduleRequestBody {
+        ScheduleRequestBody {
+            action: action.to_owned(),
+            principal_id,
+            role_definition_id: None,
+            directory_scope_id: None,
+            group_id: None,
+            access_id: None,
+            justification: Some(activation.reason.to_owned()),
+            schedule_info: Some(RequestScheduleInfo {
+                start_date_time: activation.start_date_time(),
+                expiration: RequestExpiration {
+                    expiration_type: "afterDuration".to_owned(),
+                    duration: activation.duration,
+                },
+            }),
+            ticket_info: activation.ticket.map(|ticket| TicketInfo {
+                ticket_number: ticket.number,
+                ticket_system: ticket.system,
+            }),
+        }
+    }
+
+    fn deactivation(principal_id: String) -> ScheduleRequestBody {
+        ScheduleRequestBody {
+            action: "selfDeactivate".to_owned(),
+            principal_id,
+            role_definition_id: None,
+            directory_scope_id: None,
+            group_id: None,
+            access_id: None,
+            justification: None,
+            schedule_info: None,
+            ticket_info: None,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct Expiration {
+    pub duration: Option<String>,
+    #[serde(rename = "endDateTime")]
+    pub end_date_time: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ScheduleInfo {
+    #[serde(rename = "startDateTime")]
+    pub start_date_time: Option<String>,
+    pub expiration: Option<Expiration>,
+}
+
+#[derive(Deserialize)]
+pub struct ScheduleRequest {
+    pub id: String,
+    pub status: String,
+    pub action: Option<String>,
+    #[serde(rename = "approvalId")]
+    pub approval_id: Option<String>,
+    pub justification: Option<String>,
+    #[serde(rename = "createdDateTime")]
+    pub created_date_time: String,
+    #[serde(rename = "scheduleInfo")]
+    pub schedule_info: Option<ScheduleInfo>,
+    pub principal: Option<NamedObject>,
+    // Only set for group requests
+    #[serde(rename = "accessId")]
+    pub access_id: Option<String>,
+    pub group: Option<NamedObject>,
+    // Only set for directory role requests
+    #[serde(rename = "roleDefinitionId")]
+    pub role_definition_id: Option<String>,
+    #[serde(rename = "roleDefinition")]
+    pub role_definition: Option<NamedObject>,
+    #[serde(rename = "directoryScopeId")]
+    pub directory_scope_id: Option<String>,
+    #[serde(rename = "directoryScope")]
+    pub directory_scope: Option<NamedObject>,
+}
+
+#[derive(Deserialize)]
+struct ScheduleRequests {
+    pub value: Vec<ScheduleRequest>,
+}
+
+#[derive(Deserialize)]
+struct ApprovalStep {
+    pub id: String,
+    pub status: String,
+    #[serde(rename = "assignedToMe")]
+    pub assigned_to_me: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct Approval {
+    pub steps: Vec<ApprovalStep>,
+}
+
+#[derive(Serialize)]
+struct ApprovalStepReview {
+    #[serde(rename = "reviewResult")]
+    review_result: ReviewResult,
+    justification: String,
+}
+
+#[derive(Deserialize)]
+struct Policy {
+    rules: Vec<PolicyRule>,
+}
+
+#[derive(Deserialize)]
+struct PolicyAssignment {
+    policy: Policy,
 }
 
-#[derive(Serialize, Deserialize)]
-struct GraphGroups {
-    pub value: Vec<GraphGroup>,
+#[derive(Deserialize)]
+struct PolicyAssignments {
+    value: Vec<PolicyAssignment>,
 }
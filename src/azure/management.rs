@@ -1,19 +1,22 @@
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 
-use crate::azure::LazyToken;
+use crate::azure::{json_or_error, ok_or_error, Activation, LazyToken, PolicyRule, ReviewResult};
 
-const MANAGEMENT_URL: &'static str = "https://management.azure.com";
+pub const MANAGEMENT_URL: &'static str = "https://management.azure.com";
 
 pub struct ManagementClient {
     client: Client,
     token: LazyToken,
+    // Base URL of Azure Resource Manager, also the resource tokens are requested for
+    url: String,
 }
 
 impl ManagementClient {
-    pub fn new() -> Self {
+    pub fn new(url: &str) -> Self {
         ManagementClient {
-            token: LazyToken::new(MANAGEMENT_URL),
+            token: LazyToken::new(url),
+            url: url.trim_end_matches('/').to_owned(),
             client: Client::new(),
         }
     }
@@ -22,42 +25,193 @@ impl ManagementClient {
         self.token.token().await;
     }
 
-    pub async fn get_available_roles(&self) -> Vec<Role> {
-        self.client.get(format!("{}/providers/Microsoft.Authorization/roleEligibilityScheduleInstances?api-version=2020-10-01&$filter=asTarget()", MANAGEMENT_URL))
+    pub async fn get_available_roles(&self) -> Result<Vec<Role>, String> {
+        let response = self.client.get(format!("{}/providers/Microsoft.Authorization/roleEligibilityScheduleInstances?api-version=2020-10-01&$filter=asTarget()", self.url))
             .header("Authorization", format!("Bearer {}", self.token.token().await))
             .send()
             .await
-            .unwrap()
-            .json::<RoleResponse>()
+            .map_err(|error| error.to_string())?;
+        json_or_error::<RoleResponse>(response).await.map(|roles| roles.value)
+    }
+
+    pub async fn activate_role(
+        &self,
+        activation: Activation,
+        scope: String,
+        role_definition_id: String,
+        role_assignment_id: String,
+        linked_role_eligibility_schedule_id: Option<String>,
+    ) -> Result<ScheduleRequest, String> {
+        let token = self.token.token().await;
+        let response = self.client.put(format!("{}{}/providers/Microsoft.Authorization/roleAssignmentScheduleRequests/{}?api-version=2020-10-01", self.url, scope, role_assignment_id))
+            .header("Authorization", format!("Bearer {}", token))
+            .json(&RoleAssignmentRequest {
+                properties: RoleAssignmentProperties::scheduled(
+                    "SelfActivate",
+                    role_definition_id,
+                    token.subject_id.to_owned(),
+                    linked_role_eligibility_schedule_id,
+                    activation,
+                ),
+            })
+            .send()
             .await
-            .unwrap()
-            .value
+            .unwrap();
+        json_or_error(response).await
     }
 
-    pub async fn activate_role(&self, reason: String, duration: String, scope: String, role_definition_id: String, role_assignment_id: String) -> String {
+    /// Extends an eligibility nearing its end, or renews one that has already expired
+    pub async fn renew_role(&self, activation: Activation, scope: String, role_definition_id: String, expired: bool) -> Result<ScheduleRequest, String> {
         let token = self.token.token().await;
-        self.client.put(format!("{}{}/providers/Microsoft.Authorization/roleAssignmentScheduleRequests/{}?api-version=2020-10-01", MANAGEMENT_URL, scope, role_assignment_id))
-            .header("Authorization", format!("Bearer {}", &token.access_token))
+        let response = self.client.put(format!("{}{}/providers/Microsoft.Authorization/roleEligibilityScheduleRequests/{}?api-version=2020-10-01", self.url, scope, uuid::Uuid::new_v4()))
+            .header("Authorization", format!("Bearer {}", token))
+            .json(&RoleAssignmentRequest {
+                properties: RoleAssignmentProperties::scheduled(
+                    if expired { "SelfRenew" } else { "SelfExtend" },
+                    role_definition_id,
+                    token.subject_id.to_owned(),
+                    None,
+                    activation,
+                ),
+            })
+            .send()
+            .await
+            .unwrap();
+        json_or_error(response).await
+    }
+
+    pub async fn deactivate_role(&self, scope: String, role_definition_id: String, role_assignment_id: String) -> Result<ScheduleRequest, String> {
+        let token = self.token.token().await;
+        let response = self.client.put(format!("{}{}/providers/Microsoft.Authorization/roleAssignmentScheduleRequests/{}?api-version=2020-10-01", self.url, scope, role_assignment_id))
+            .header("Authorization", format!("Bearer {}", token))
             .json(&RoleAssignmentRequest {
                 properties: RoleAssignmentProperties {
                     role_definition_id,
                     principal_id: token.subject_id.to_owned(),
-                    request_type: "SelfActivate".to_string(),
-                    justification: reason,
-                    schedule_info: RoleAssignmentScheduleInfo {
-                        expiration: RoleAssignmentExpiration {
-                            expiry_type: "AfterDuration".to_string(),
-                            duration,
-                        },
-                    },
+                    request_type: "SelfDeactivate".to_string(),
+                    linked_role_eligibility_schedule_id: None,
+                    justification: None,
+                    schedule_info: None,
+                    ticket_info: None,
                 },
             })
             .send()
             .await
-            .unwrap()
-            .text()
+            .unwrap();
+        json_or_error(response).await
+    }
+
+    /// Rules of the role management policy for a role at a scope, empty if no policy is assigned
+    pub async fn get_policy_rules(&self, scope: &str, role_definition_id: &str) -> Result<Vec<PolicyRule>, String> {
+        let response = self.client.get(format!("{}{}/providers/Microsoft.Authorization/roleManagementPolicyAssignments?api-version=2020-10-01&$filter=roleDefinitionId eq '{}'", self.url, scope, role_definition_id))
+            .header("Authorization", format!("Bearer {}", self.token.token().await))
+            .send()
+            .await
+            .map_err(|error| error.to_string())?;
+        Ok(json_or_error::<PolicyAssignmentResponse>(response).await?
+            .value
+            .into_iter()
+            .next()
+            .map(|assignment| assignment.properties.effective_rules)
+            .unwrap_or_default())
+    }
+
+    pub async fn get_active_roles(&self) -> Result<Vec<ActiveRole>, String> {
+        let response = self.client.get(format!("{}/providers/Microsoft.Authorization/roleAssignmentScheduleInstances?api-version=2020-10-01&$filter=asTarget()", self.url))
+            .header("Authorization", format!("Bearer {}", self.token.token().await))
+            .send()
+            .await
+            .map_err(|error| error.to_string())?;
+        Ok(json_or_error::<ActiveRoleResponse>(response).await?.value)
+    }
+
+    pub async fn get_role_assignment_request(&self, scope: &str, name: &str) -> Result<ScheduleRequest, String> {
+        let response = self.client.get(format!("{}{}/providers/Microsoft.Authorization/roleAssignmentScheduleRequests/{}?api-version=2020-10-01", self.url, scope, name))
+            .header("Authorization", format!("Bearer {}", self.token.token().await))
+            .send()
+            .await
+            .map_err(|error| error.to_string())?;
+        json_or_error(response).await
+    }
+
+    /// Requests the signed-in user has made at a scope
+    pub async fn get_requests(&self, scope: &str) -> Result<Vec<ScheduleRequest>, String> {
+        let response = self.client.get(format!("{}{}/providers/Microsoft.Authorization/roleAssignmentScheduleRequests?api-version=2020-10-01&$filter=asRequestor()", self.url, scope))
+            .header("Authorization", format!("Bearer {}", self.token.token().await))
+            .send()
+            .await
+            .map_err(|error| error.to_string())?;
+        json_or_error::<ScheduleRequests>(response).await.map(|requests| requests.value)
+    }
+
+    pub async fn cancel_request(&self, scope: &str, name: &str) -> Result<(), String> {
+        let response = self.client.post(format!("{}{}/providers/Microsoft.Authorization/roleAssignmentScheduleRequests/{}/cancel?api-version=2020-10-01", self.url, scope, name))
+            .header("Authorization", format!("Bearer {}", self.token.token().await))
+            .header("Content-Length", "0")
+            .send()
+            .await
+            .unwrap();
+        ok_or_error(response).await
+    }
+
+    /// Subscriptions the signed-in user can read
+    pub async fn get_subscriptions(&self) -> Result<Vec<Subscription>, String> {
+        let response = self.client.get(format!("{}/subscriptions?api-version=2020-01-01", self.url))
+            .header("Authorization", format!("Bearer {}", self.token.token().await))
+            .send()
+            .await
+            .map_err(|error| error.to_string())?;
+        json_or_error::<Subscriptions>(response).await.map(|subscriptions| subscriptions.value)
+    }
+
+    /// Management groups and subscriptions the signed-in user can see, with the management group each belongs to
+    pub async fn get_entities(&self) -> Result<Vec<Entity>, String> {
+        let response = self.client.post(format!("{}/providers/Microsoft.Management/getEntities?api-version=2020-05-01", self.url))
+            .header("Authorization", format!("Bearer {}", self.token.token().await))
+            .header("Content-Length", "0")
+            .send()
+            .await
+            .map_err(|error| error.to_string())?;
+        json_or_error::<Entities>(response).await.map(|entities| entities.value)
+    }
+
+    pub async fn get_requests_to_approve(&self) -> Result<Vec<ScheduleRequest>, String> {
+        let response = self.client.get(format!("{}/providers/Microsoft.Authorization/roleAssignmentScheduleRequests?api-version=2020-10-01&$filter=asApprover()", self.url))
+            .header("Authorization", format!("Bearer {}", self.token.token().await))
+            .send()
             .await
-            .unwrap()
+            .map_err(|error| error.to_string())?;
+        Ok(json_or_error::<ScheduleRequests>(response).await?
+            .value
+            .into_iter()
+            .filter(|request| request.properties.status == "PendingApproval")
+            .collect())
+    }
+
+    /// Reviews the stage of an approval that is currently waiting for the signed-in user
+    pub async fn review_request(&self, approval_id: &str, review_result: ReviewResult, justification: String) -> Result<(), String> {
+        let token = self.token.token().await;
+        let response = self.client.get(format!("{}{}?api-version=2021-01-01-preview", self.url, approval_id))
+            .header("Authorization", format!("Bearer {}", token))
+            .send()
+            .await
+            .unwrap();
+        let approval = json_or_error::<Approval>(response).await?;
+        let stage = approval.properties.stages.into_iter()
+            .find(|stage| stage.properties.status == "InProgress" && stage.properties.assigned_to_me.unwrap_or(false))
+            .ok_or_else(|| "No approval stage is waiting for you".to_owned())?;
+        let response = self.client.put(format!("{}{}/stages/{}?api-version=2021-01-01-preview", self.url, approval_id, stage.name))
+            .header("Authorization", format!("Bearer {}", token))
+            .json(&ApprovalStageReview {
+                properties: ApprovalStageReviewProperties {
+                    review_result,
+                    justification,
+                },
+            })
+            .send()
+            .await
+            .unwrap();
+        ok_or_error(response).await
     }
 }
 
@@ -73,12 +227,22 @@ pub struct ExpandedProperties {
     #[serde(rename = "roleDefinition")]
     pub role_definition: RoleNamedResource,
     pub scope: RoleNamedResource,
+    #[serde(default, skip_serializing)]
+    pub principal: Option<RoleNamedResource>,
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct RoleProperties {
     #[serde(rename = "roleDefinitionId")]
     pub role_definition_id: String,
+    #[serde(rename = "roleEligibilityScheduleId")]
+    pub role_eligibility_schedule_id: Option<String>,
+    // `Direct`, `Group` or `Inherited`
+    #[serde(rename = "memberType")]
+    pub member_type: Option<String>,
+    // Not set for permanent eligibilities
+    #[serde(rename = "endDateTime")]
+    pub end_date_time: Option<String>,
     #[serde(rename = "expandedProperties")]
     pub expanded_properties: ExpandedProperties,
 }
@@ -95,6 +259,29 @@ struct RoleResponse {
     pub value: Vec<Role>,
 }
 
+#[derive(Deserialize)]
+pub struct ActiveRoleProperties {
+    pub scope: String,
+    #[serde(rename = "roleDefinitionId")]
+    pub role_definition_id: String,
+    #[serde(rename = "assignmentType")]
+    pub assignment_type: Option<String>,
+    #[serde(rename = "startDateTime")]
+    pub start_date_time: Option<String>,
+    #[serde(rename = "endDateTime")]
+    pub end_date_time: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ActiveRole {
+    pub properties: ActiveRoleProperties,
+}
+
+#[derive(Deserialize)]
+struct ActiveRoleResponse {
+    pub value: Vec<ActiveRole>,
+}
+
 #[derive(Serialize)]
 struct RoleAssignmentRequest {
     #[serde(rename = "Properties")]
@@ -109,14 +296,58 @@ struct RoleAssignmentProperties {
     principal_id: String,
     #[serde(rename = "RequestType")]
     request_type: String,
-    #[serde(rename = "Justification")]
-    justification: String,
-    #[serde(rename = "ScheduleInfo")]
-    schedule_info: RoleAssignmentScheduleInfo,
+    #[serde(rename = "LinkedRoleEligibilityScheduleId", skip_serializing_if = "Option::is_none")]
+    linked_role_eligibility_schedule_id: Option<String>,
+    #[serde(rename = "Justification", skip_serializing_if = "Option::is_none")]
+    justification: Option<String>,
+    #[serde(rename = "ScheduleInfo", skip_serializing_if = "Option::is_none")]
+    schedule_info: Option<RoleAssignmentScheduleInfo>,
+    #[serde(rename = "TicketInfo", skip_serializing_if = "Option::is_none")]
+    ticket_info: Option<TicketInfo>,
+}
+
+impl RoleAssignmentProperties {
+    /// Request of the signed-in user scheduled with the duration, reason and ticket of the activation
+    fn scheduled(
+        request_type: &str,
+        role_definition_id: String,
+        principal_id: String,
+        linked_role_eligibility_schedule_id: Option<String>,
+        activation: Activation,
+    ) -> RoleAssignmentProperties {
+        RoleAssignmentProperties {
+            role_definition_id,
+            principal_id,
+            request_type: request_type.to_owned(),
+            linked_role_eligibility_schedule_id,
+            justification: Some(activation.reason.to_owned()),
+            schedule_info: Some(RoleAssignmentScheduleInfo {
+                start_date_time: activation.start_date_time(),
+                expiration: RoleAssignmentExpiration {
+                    expiry_type: "AfterDuration".to_string(),
+                    duration: activation.duration,
+                },
+            }),
+            ticket_info: activation.ticket.map(|ticket| TicketInfo {
+                ticket_number: ticket.number,
+                ticket_system: ticket.system,
+            }),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct TicketInfo {
+    #[serde(rename = "TicketNumber")]
+    ticket_number: String,
+    #[serde(rename = "TicketSystem")]
+    ticket_system: String,
 }
 
 #[derive(Serialize)]
 struct RoleAssignmentScheduleInfo {
+    #[serde(rename = "StartDateTime", skip_serializing_if = "Option::is_none")]
+    start_date_time: Option<String>,
     #[serde(rename = "Expiration")]
     expiration: RoleAssignmentExpiration,
 }
@@ -129,3 +360,139 @@ struct RoleAssignmentExpiration {
     #[serde(rename = "Duration")]
     duration: String,
 }
+
+#[derive(Deserialize)]
+pub struct Expiration {
+    pub duration: Option<String>,
+    #[serde(rename = "endDateTime")]
+    pub end_date_time: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ScheduleInfo {
+    #[serde(rename = "startDateTime")]
+    pub start_date_time: Option<String>,
+    pub expiration: Option<Expiration>,
+}
+
+#[derive(Deserialize)]
+pub struct ScheduleRequestProperties {
+    pub status: String,
+    pub scope: Option<String>,
+    #[serde(rename = "roleDefinitionId")]
+    pub role_definition_id: Option<String>,
+    #[serde(rename = "requestType")]
+    pub request_type: Option<String>,
+    #[serde(rename = "approvalId")]
+    pub approval_id: Option<String>,
+    pub justification: Option<String>,
+    #[serde(rename = "createdOn")]
+    pub created_on: Option<String>,
+    #[serde(rename = "scheduleInfo")]
+    pub schedule_info: Option<ScheduleInfo>,
+    #[serde(rename = "expandedProperties")]
+    pub expanded_properties: Option<ExpandedProperties>,
+}
+
+#[derive(Deserialize)]
+pub struct ScheduleRequest {
+    pub name: String,
+    pub properties: ScheduleRequestProperties,
+}
+
+#[derive(Deserialize)]
+struct ScheduleRequests {
+    pub value: Vec<ScheduleRequest>,
+}
+
+#[derive(Deserialize)]
+struct ApprovalStageProperties {
+    pub status: String,
+    #[serde(rename = "assignedToMe")]
+    pub assigned_to_me: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct ApprovalStage {
+    pub name: String,
+    pub properties: ApprovalStageProperties,
+}
+
+#[derive(Deserialize)]
+struct ApprovalProperties {
+    pub stages: Vec<ApprovalStage>,
+}
+
+#[derive(Deserialize)]
+struct Approval {
+    pub properties: ApprovalProperties,
+}
+
+#[derive(Serialize)]
+struct ApprovalStageReviewProperties {
+    #[serde(rename = "reviewResult")]
+    review_result: ReviewResult,
+    justification: String,
+}
+
+#[derive(Serialize)]
+struct ApprovalStageReview {
+    properties: ApprovalStageReviewProperties,
+}
+
+#[derive(Deserialize)]
+struct PolicyAssignmentProperties {
+    #[serde(rename = "effectiveRules")]
+    effective_rules: Vec<PolicyRule>,
+}
+
+#[derive(Deserialize)]
+struct PolicyAssignment {
+    properties: PolicyAssignmentProperties,
+}
+
+#[derive(Deserialize)]
+struct PolicyAssignmentResponse {
+    value: Vec<PolicyAssignment>,
+}
+
+#[derive(Deserialize)]
+pub struct Subscription {
+    #[serde(rename = "subscriptionId")]
+    pub subscription_id: String,
+    #[serde(rename = "displayName")]
+    pub display_name: String,
+}
+
+#[derive(Deserialize)]
+struct Subscriptions {
+    value: Vec<Subscription>,
+}
+
+#[derive(Deserialize)]
+pub struct EntityParent {
+    pub id: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct EntityProperties {
+    #[serde(rename = "displayName")]
+    pub display_name: Option<String>,
+    pub parent: Option<EntityParent>,
+}
+
+/// A management group or a subscription in the management group hierarchy
+#[derive(Deserialize)]
+pub struct Entity {
+    pub id: String,
+    // `Microsoft.Management/managementGroups` or `/subscriptions`
+    #[serde(rename = "type")]
+    pub entity_type: String,
+    pub name: String,
+    pub properties: EntityProperties,
+}
+
+#[derive(Deserialize)]
+struct Entities {
+    value: Vec<Entity>,
+}
@@ -1,28 +1,128 @@
 use std::fmt::{Display, Formatter};
 use base64::Engine;
 use base64::prelude::BASE64_URL_SAFE_NO_PAD;
+use chrono::{DateTime, SecondsFormat, Utc};
 use futures::lock::Mutex;
-
+use reqwest::Response;
+use serde::{Deserialize, Serialize};
+use serde::de::DeserializeOwned;
 use serde_json::Value;
 
 pub mod graph;
 pub mod management;
-pub mod pim;
+
+/// Parses a successful response as json, or returns the raw body of a failed one
+async fn json_or_error<T: DeserializeOwned>(response: Response) -> Result<T, String> {
+    if response.status().is_success() {
+        Ok(response.json::<T>().await.unwrap())
+    } else {
+        Err(response.text().await.unwrap())
+    }
+}
+
+/// Returns the raw body of a failed response
+async fn ok_or_error(response: Response) -> Result<(), String> {
+    if response.status().is_success() {
+        Ok(())
+    } else {
+        Err(response.text().await.unwrap())
+    }
+}
+
+/// Change ticket an activation is made for
+#[derive(Clone)]
+pub struct Ticket {
+    pub number: String,
+    pub system: String,
+}
+
+/// Details of a requested activation
+#[derive(Clone)]
+pub struct Activation {
+    pub reason: String,
+    // ISO8601 duration
+    pub duration: String,
+    // Activation starts immediately if not set
+    pub start: Option<DateTime<Utc>>,
+    pub ticket: Option<Ticket>,
+}
+
+impl Activation {
+    fn start_date_time(&self) -> Option<String> {
+        self.start.map(|start| start.to_rfc3339_opts(SecondsFormat::Secs, true))
+    }
+}
+
+/// Rule of a role management policy, Microsoft Graph and ARM share the same rule ids and properties
+#[derive(Deserialize)]
+pub struct PolicyRule {
+    pub id: String,
+    #[serde(rename = "enabledRules")]
+    pub enabled_rules: Option<Vec<String>>,
+    // ISO8601 duration, only set on expiration rules
+    #[serde(rename = "maximumDuration")]
+    pub maximum_duration: Option<String>,
+    // Only set on approval rules
+    pub setting: Option<ApprovalSetting>,
+}
+
+#[derive(Deserialize)]
+pub struct ApprovalSetting {
+    #[serde(rename = "isApprovalRequired", default)]
+    pub is_approval_required: bool,
+}
+
+/// Decision of an approver on a pending request
+#[derive(Clone, Copy, Serialize)]
+pub enum ReviewResult {
+    Approve,
+    Deny,
+}
 
 pub struct LazyToken {
     resource_uri: String,
+    // Delegated permissions the token is requested with, the permissions the Azure CLI has by default if empty
+    scopes: Vec<String>,
     token: Mutex<Option<Token>>
 }
 
 impl LazyToken {
     fn new<S: Into<String>>(resource_uri: S) -> LazyToken {
+        LazyToken::with_scopes(resource_uri, vec![])
+    }
+
+    fn with_scopes<S: Into<String>>(resource_uri: S, scopes: Vec<String>) -> LazyToken {
         LazyToken {
             resource_uri: resource_uri.into(),
+            scopes,
             token: Mutex::new(None)
         }
     }
 
-    async fn fetch_token(resource_uri: &str) -> Token {
+    /// Fetches a token with the scopes, falling back to the default permissions of the Azure CLI with a warning if
+    /// they can not be granted, in which case requests needing the scopes fail with the error returned by the API
+    async fn fetch_scoped_token(&self) -> Token {
+        if !self.scopes.is_empty() {
+            let mut arguments = vec!["--scope".to_owned()];
+            arguments.extend(self.scopes.iter().cloned());
+            match Self::fetch_token(&arguments).await {
+                Ok(token) => return token,
+                Err(error) => eprintln!(
+                    "Warning: a token for {} with the permissions {} could not be fetched, using the default permissions of the Azure CLI: {}",
+                    self.resource_uri,
+                    self.scopes.join(" "),
+                    error
+                ),
+            }
+        }
+        Self::fetch_token(&["--resource".to_owned(), self.resource_uri.to_owned()]).await.unwrap_or_else(|error| {
+            eprintln!("Failed to fetch a token for {}: {}", self.resource_uri, error);
+            std::process::exit(1);
+        })
+    }
+
+    /// Runs `az account get-access-token` with the arguments, returning what it printed if it failed
+    async fn fetch_token(arguments: &[String]) -> Result<Token, String> {
         #[cfg(target_family = "unix")]
         const EXECUTABLE: &'static str = "az";
         #[cfg(target_family = "windows")]
@@ -31,20 +131,22 @@ impl LazyToken {
         let output = tokio::process::Command::new(EXECUTABLE)
             .arg("account")
             .arg("get-access-token")
-            .arg("--resource")
-            .arg(resource_uri)
+            .args(arguments)
             .output()
             .await
             .unwrap();
+        if !output.status.success() {
+            return Err(String::from_utf8_lossy(&output.stderr).trim().to_owned());
+        }
         let json: Value = serde_json::from_slice(&output.stdout).unwrap();
         let access_token = json.get("accessToken").unwrap().as_str().unwrap().to_string();
         let claims_base64 = access_token.split(".").skip(1).next().unwrap();
         let claims = serde_json::from_slice::<Value>(&BASE64_URL_SAFE_NO_PAD.decode(claims_base64).unwrap()).unwrap();
-        Token {
+        Ok(Token {
             subject_id: claims.get("oid").unwrap().as_str().unwrap().to_owned(),
             access_token,
             tenant_id: claims.get("tid").unwrap().as_str().unwrap().to_owned()
-        }
+        })
     }
 
     pub async fn token(&self) -> Token {
@@ -52,7 +154,7 @@ impl LazyToken {
         if let Some(token) = handle.as_ref() {
             token.clone()
         } else {
-            *handle = Some(Self::fetch_token(&self.resource_uri).await);
+            *handle = Some(self.fetch_scoped_token().await);
             handle.as_ref().unwrap().clone()
         }
     }
@@ -1,81 +1,225 @@
-use std::future::Future;
+use std::future::{Future, join};
 use std::pin::Pin;
 
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
-use crate::azure::pim::PimClient;
-use crate::cmd::pim::Pim;
+use crate::azure::Activation;
+use crate::azure::graph::GraphClient;
+use crate::cmd::pim::{parse_time, submitted, ActiveAssignment, ActiveAssignments, Pim, Source};
+use crate::cmd::pim::policy::{attach_policies, Policy};
 
-pub async fn fetch_aad_role_info(pim_client: &PimClient) -> Vec<AadRoleInfo> {
-    let groups = pim_client.fetch_group_pim().await;
-    if groups.is_empty() {
-        return vec![];
-    }
-    pim_client.get_aad_roles().await
+pub async fn fetch_aad_role_info(graph_client: &GraphClient) -> Result<Vec<AadRoleInfo>, String> {
+    let (aad_roles, organization_name) = join!(
+        graph_client.get_eligible_directory_roles(),
+        graph_client.get_organization_name()
+    ).await;
+    let organization_name = organization_name.unwrap_or_else(|| "Directory".to_owned());
+    let aad_role_info = aad_roles?
         .into_iter()
-        .map(|aad_role| AadRoleInfo {
-            role_assignment_id: aad_role.id,
-            tenant_id: aad_role.resourceId,
-            role_definition_id: aad_role.roleDefinitionId,
-            role_definition_name: aad_role.roleDefinition.displayName,
-            role_name: aad_role.roleDefinition.resource.displayName,
-            role_description: None,
+        .map(|aad_role| {
+            let (role_definition_name, role_description) = aad_role.role_definition
+                .map(|role_definition| (role_definition.display_name.unwrap_or_default(), role_definition.description))
+                .unwrap_or_default();
+            AadRoleInfo {
+                role_assignment_id: aad_role.id,
+                directory_scope_name: aad_role.directory_scope.and_then(|scope| scope.display_name),
+                directory_scope_id: aad_role.directory_scope_id,
+                role_definition_id: aad_role.role_definition_id,
+                role_definition_name,
+                role_name: organization_name.to_owned(),
+                role_description,
+                eligibility_end: aad_role.end_date_time,
+                policy: None,
+            }
         })
-        .collect()
+        .collect::<Vec<AadRoleInfo>>();
+
+    Ok(attach_policies(
+        aad_role_info,
+        |info| fetch_policy(graph_client, info.role_definition_id.to_owned()),
+        |info, policy| AadRoleInfo { policy, ..info },
+    ).await)
+}
+
+async fn fetch_policy(graph_client: &GraphClient, role_definition_id: String) -> Result<Policy, String> {
+    Ok(Policy::from_rules(&graph_client.get_policy_rules("/", "DirectoryRole", &role_definition_id).await?))
+}
+
+pub async fn fetch_active_assignments(graph_client: &GraphClient) -> Result<ActiveAssignments, String> {
+    Ok(graph_client.get_active_directory_roles().await?
+        .into_iter()
+        .filter(|aad_role| aad_role.assignment_type.as_deref() == Some("Activated"))
+        .map(|aad_role| (
+            (aad_role.directory_scope_id, aad_role.role_definition_id),
+            ActiveAssignment::new(aad_role.start_date_time, aad_role.end_date_time),
+        ))
+        .collect())
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct AadRoleInfo {
     pub role_assignment_id: String,
-    pub tenant_id: String,
+    // `/` for tenant wide roles, otherwise an administrative unit or application
+    pub directory_scope_id: String,
+    #[serde(default)]
+    pub directory_scope_name: Option<String>,
     pub role_definition_id: String,
     pub role_definition_name: String,
     pub role_name: String,
     pub role_description: Option<String>,
+    #[serde(default)]
+    pub eligibility_end: Option<String>,
+    #[serde(default)]
+    pub policy: Option<Policy>,
+}
+
+impl AadRoleInfo {
+    pub fn assignment_key(&self) -> (String, String) {
+        (self.directory_scope_id.to_owned(), self.role_definition_id.to_owned())
+    }
+
+    /// The scope the role is eligible at, like `AU Oslo`, or None for tenant wide roles
+    fn scope_label(&self) -> Option<String> {
+        if self.directory_scope_id == "/" {
+            return None;
+        }
+        let name = self.directory_scope_name.clone().unwrap_or_else(|| self.directory_scope_id.to_owned());
+        if self.directory_scope_id.starts_with("/administrativeUnits/") {
+            Some(format!("AU {}", name))
+        } else {
+            Some(name)
+        }
+    }
 }
 
 pub struct AadRolePim<'a> {
-    pim_client: &'a PimClient,
+    graph_client: &'a GraphClient,
     aad_role_info: AadRoleInfo,
+    active: Option<ActiveAssignment>,
 }
 
 impl<'a> AadRolePim<'a> {
-    pub fn new(pim_client: &'a PimClient, aad_role_info: AadRoleInfo) -> AadRolePim<'a> {
+    pub fn new(graph_client: &'a GraphClient, aad_role_info: AadRoleInfo, active: Option<ActiveAssignment>) -> AadRolePim<'a> {
         AadRolePim {
-            pim_client,
+            graph_client,
             aad_role_info,
+            active,
         }
     }
 }
 
 impl<'a> Pim<'a> for AadRolePim<'a> {
+    fn source(&self) -> Source {
+        Source::EntraRole
+    }
+
     fn group_by(&self) -> String {
         self.aad_role_info.role_description.clone().unwrap_or(self.aad_role_info.role_name.to_owned())
     }
 
     fn resource_id(&self) -> String {
-        format!("{} ({})", self.aad_role_info.role_name, self.aad_role_info.role_definition_name)
+        format!("{} ({})", self.aad_role_info.role_name, self.name())
+    }
+
+    fn name(&self) -> String {
+        match self.aad_role_info.scope_label() {
+            Some(scope) => format!("{} @ {}", self.aad_role_info.role_definition_name, scope),
+            None => self.aad_role_info.role_definition_name.to_owned(),
+        }
+    }
+
+    fn selectors(&self) -> Vec<String> {
+        vec![
+            self.name(),
+            self.resource_id(),
+            self.aad_role_info.role_definition_id.to_owned(),
+            self.aad_role_info.role_assignment_id.to_owned(),
+        ]
     }
 
     fn ensure_token(&self) -> Pin<Box<dyn Future<Output=()> + 'a>> {
-        Box::pin(self.pim_client.ensure_token())
+        Box::pin(self.graph_client.ensure_token())
+    }
+
+    fn activate(&self, activation: Activation) -> Pin<Box<dyn Future<Output=Option<String>> + 'a>> {
+        let graph_client = self.graph_client;
+        let name = self.name();
+        let directory_scope_id = self.aad_role_info.directory_scope_id.to_owned();
+        let role_definition_id = self.aad_role_info.role_definition_id.to_owned();
+        Box::pin(async move {
+            let result = graph_client.request_directory_role(activation, directory_scope_id, role_definition_id).await;
+            submitted(name, result.map(|request| (request.id, request.status)))
+        })
+    }
+
+    fn request_status(&self, request_id: String) -> Pin<Box<dyn Future<Output=Result<String, String>> + 'a>> {
+        let graph_client = self.graph_client;
+        Box::pin(async move {
+            graph_client.get_directory_role_request(&request_id).await.map(|request| request.status)
+        })
+    }
+
+    fn active(&self) -> Option<&ActiveAssignment> {
+        self.active.as_ref()
+    }
+
+    fn deactivate(&self) -> Pin<Box<dyn Future<Output=Option<String>> + 'a>> {
+        let graph_client = self.graph_client;
+        let name = self.name();
+        let directory_scope_id = self.aad_role_info.directory_scope_id.to_owned();
+        let role_definition_id = self.aad_role_info.role_definition_id.to_owned();
+        Box::pin(async move {
+            let result = graph_client.request_directory_role_deactivation(directory_scope_id, role_definition_id).await;
+            submitted(name, result.map(|request| (request.id, request.status)))
+        })
+    }
+
+    fn policy(&self) -> Pin<Box<dyn Future<Output=Result<Policy, String>> + 'a>> {
+        let graph_client = self.graph_client;
+        let cached = self.aad_role_info.policy.clone();
+        let role_definition_id = self.aad_role_info.role_definition_id.to_owned();
+        Box::pin(async move {
+            match cached {
+                Some(policy) => Ok(policy),
+                None => fetch_policy(graph_client, role_definition_id).await,
+            }
+        })
+    }
+
+    fn cached_policy(&self) -> Option<&Policy> {
+        self.aad_role_info.policy.as_ref()
+    }
+
+    fn last_reason(&self) -> Pin<Box<dyn Future<Output=Option<String>> + 'a>> {
+        let graph_client = self.graph_client;
+        let name = self.name();
+        let directory_scope_id = self.aad_role_info.directory_scope_id.to_owned();
+        let role_definition_id = self.aad_role_info.role_definition_id.to_owned();
+        Box::pin(async move {
+            graph_client.get_directory_role_requests(&directory_scope_id, &role_definition_id).await
+                .map_err(|error| eprintln!("Warning: failed to read the previous requests for {}: {}", name, error))
+                .ok()?
+                .into_iter()
+                .filter(|request| request.action.as_deref() == Some("selfActivate"))
+                .max_by(|a, b| a.created_date_time.cmp(&b.created_date_time))
+                .and_then(|request| request.justification)
+        })
+    }
+
+    fn eligibility_end(&self) -> Option<DateTime<Utc>> {
+        self.aad_role_info.eligibility_end.as_deref().and_then(parse_time)
     }
 
-    fn activate(&self, reason: String, duration: String) -> Pin<Box<dyn Future<Output=()> + 'a>> {
-        let tenant_id = self.aad_role_info.tenant_id.to_owned();
-        let role_assignment_id = self.aad_role_info.role_assignment_id.to_owned();
+    fn renew(&self, activation: Activation) -> Pin<Box<dyn Future<Output=Option<String>> + 'a>> {
+        let graph_client = self.graph_client;
+        let name = self.name();
+        let directory_scope_id = self.aad_role_info.directory_scope_id.to_owned();
         let role_definition_id = self.aad_role_info.role_definition_id.to_owned();
-        Box::pin(async {
-            println!(
-                "{}",
-                self.pim_client.request_aad_role_access(
-                    reason,
-                    duration,
-                    tenant_id,
-                    role_assignment_id,
-                    role_definition_id,
-                ).await
-            );
+        let expired = self.eligibility_end().is_some_and(|end| end <= Utc::now());
+        Box::pin(async move {
+            let result = graph_client.request_directory_role_renewal(activation, directory_scope_id, role_definition_id, expired).await;
+            submitted(name, result.map(|request| (request.id, request.status)))
         })
     }
 }
@@ -0,0 +1,243 @@
+use clap::Args;
+
+use crate::azure::Activation;
+use crate::cmd::pim::config::{Config, Profile};
+use crate::cmd::pim::policy::RequestedDuration;
+use crate::cmd::pim::{activate_all, policy, unique, Pim, PimArgs, Source};
+
+#[derive(Args)]
+pub struct ActivateArgs {
+    #[arg(long = "role", required = true, help = "Name or id of a role to activate, can be repeated")]
+    roles: Vec<String>,
+    #[arg(
+        short,
+        long,
+        value_parser = RequestedDuration::parse,
+        help = "Duration like `30m`, `1h30m`, `PT1H` or `max`, defaults to the configured duration or the maximum allowed for each role"
+    )]
+    duration: Option<RequestedDuration>,
+    #[arg(long = "role-duration", help = "Duration of one of the roles like `\"Global Reader=30m\"`, can be repeated")]
+    role_durations: Vec<String>,
+    #[arg(long, help = "Justification for the activation, defaults to the configured reason")]
+    reason: Option<String>,
+    #[arg(short, long, value_enum, help = "Only match roles from this source")]
+    source: Option<Source>,
+}
+
+pub async fn activate<'a>(
+    args: &ActivateArgs,
+    pim_args: &PimArgs,
+    config: &Config,
+    pims: &[&'a dyn Pim<'a>],
+) -> Vec<(&'a dyn Pim<'a>, Option<String>)> {
+    let selected = match resolve(&args.roles, args.source, pims) {
+        Ok(selected) => selected,
+        Err(message) => {
+            eprintln!("{}", message);
+            std::process::exit(1);
+        }
+    };
+
+    let Some(reason) = args.reason.clone().or_else(|| config.reason()) else {
+        eprintln!("No reason is configured, use --reason");
+        std::process::exit(1);
+    };
+
+    let overrides = match role_durations(&args.role_durations, args.source, &selected) {
+        Ok(overrides) => overrides,
+        Err(message) => {
+            eprintln!("{}", message);
+            std::process::exit(1);
+        }
+    };
+    let duration = args.duration.unwrap_or_else(|| config.duration());
+    submit(selected, duration, &overrides, reason, pim_args).await
+}
+
+/// Activates the roles of a profile, described like "the profile 'morning'" in messages. Selectors that no longer match
+/// exactly one eligible role are reported and skipped, returning false as the second value if any were.
+pub async fn activate_profile<'a>(
+    description: &str,
+    profile: &Profile,
+    pim_args: &PimArgs,
+    config: &Config,
+    pims: &[&'a dyn Pim<'a>],
+) -> (Vec<(&'a dyn Pim<'a>, Option<String>)>, bool) {
+    let (selected, complete) = resolve_profile(description, profile, pims);
+    if selected.is_empty() {
+        eprintln!("None of the roles of {} match an eligible role", description);
+        std::process::exit(1);
+    }
+
+    let Some(reason) = profile.reason().or_else(|| config.reason()) else {
+        eprintln!("No reason is configured for {} and there is no default reason", description);
+        std::process::exit(1);
+    };
+    let duration = profile.duration().unwrap_or_else(|| config.duration());
+    (submit(selected, duration, &[], reason, pim_args).await, complete)
+}
+
+/// Resolves the roles of a profile, warning about the selectors that no longer match exactly one eligible role and
+/// returning false as the second value if any did not
+pub fn resolve_profile<'a>(description: &str, profile: &Profile, pims: &[&'a dyn Pim<'a>]) -> (Vec<&'a dyn Pim<'a>>, bool) {
+    let mut selected = Vec::with_capacity(profile.roles.len());
+    let mut unmatched = Vec::new();
+    for role in &profile.roles {
+        match resolve(std::slice::from_ref(role), None, pims) {
+            Ok(pims) => selected.extend(pims),
+            Err(message) => unmatched.push(message),
+        }
+    }
+    if !unmatched.is_empty() {
+        eprintln!("Warning: roles of {} that no longer match an eligible role:\n{}", description, unmatched.join("\n"));
+    }
+    (unique(selected.into_iter()), unmatched.is_empty())
+}
+
+/// Checks the activation of every role against its policy before submitting them, exiting if any would be rejected
+async fn submit<'a>(
+    selected: Vec<&'a dyn Pim<'a>>,
+    duration: RequestedDuration,
+    overrides: &[Option<RequestedDuration>],
+    reason: String,
+    pim_args: &PimArgs,
+) -> Vec<(&'a dyn Pim<'a>, Option<String>)> {
+    let policies = policy::policies(&selected).await;
+    let durations = match policy::durations(&selected, &policies, duration, overrides) {
+        Ok(durations) => durations,
+        Err(message) => {
+            eprintln!("{}", message);
+            std::process::exit(1);
+        }
+    };
+
+    let requests = selected.into_iter()
+        .zip(durations)
+        .map(|(pim, duration)| (pim, Activation {
+            reason: reason.clone(),
+            duration,
+            start: pim_args.start,
+            ticket: pim_args.ticket(),
+        }))
+        .collect::<Vec<_>>();
+    if let Err(violations) = policy::check(&requests, &policies) {
+        eprintln!("{}", violations);
+        std::process::exit(1);
+    }
+    activate_all(requests).await
+}
+
+/// Resolves each `selector=duration` to the selected role it overrides the duration of
+fn role_durations<'a>(
+    role_durations: &[String],
+    source: Option<Source>,
+    selected: &[&'a dyn Pim<'a>],
+) -> Result<Vec<Option<RequestedDuration>>, String> {
+    let mut overrides = vec![None; selected.len()];
+    for role_duration in role_durations {
+        let Some((selector, duration)) = role_duration.rsplit_once('=') else {
+            return Err(format!("'{}' must be a role and a duration like `\"Global Reader=30m\"`", role_duration));
+        };
+        let duration = RequestedDuration::parse(duration)?;
+        let role = resolve(&[selector.trim().to_owned()], source, selected)?[0];
+        let index = selected.iter()
+            .position(|pim| std::ptr::addr_eq(*pim, role))
+            .unwrap();
+        overrides[index] = Some(duration);
+    }
+    Ok(overrides)
+}
+
+/// Resolves every selector to exactly one role, reporting all selectors that did not. Selectors matching the same
+/// role select it once.
+pub fn resolve<'a>(
+    selectors: &[String],
+    source: Option<Source>,
+    pims: &[&'a dyn Pim<'a>],
+) -> Result<Vec<&'a dyn Pim<'a>>, String> {
+    let mut selected: Vec<&dyn Pim> = Vec::with_capacity(selectors.len());
+    let mut errors = Vec::new();
+    for selector in selectors {
+        let matches = pims.iter()
+            .filter(|pim| source.is_none_or(|source| pim.source() == source))
+            .filter(|pim| pim.selectors().iter().any(|value| value.eq_ignore_ascii_case(selector)))
+            .collect::<Vec<_>>();
+        match matches.as_slice() {
+            [] => errors.push(format!("No eligible role matches '{}'", selector)),
+            [pim] => selected.push(**pim),
+            _ => errors.push(format!(
+                "'{}' is ambiguous, it matches:\n{}",
+                selector,
+                matches.iter().map(|pim| format!("\t{}", pim.name())).collect::<Vec<_>>().join("\n")
+            )),
+        }
+    }
+    if errors.is_empty() {
+        Ok(unique(selected.into_iter()))
+    } else {
+        Err(errors.join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cmd::pim::test_pim::TestPim;
+    use crate::cmd::pim::{Pim, Source};
+
+    use super::resolve;
+
+    fn role(name: &str, source: Source, selectors: &[&str]) -> TestPim {
+        TestPim {
+            source,
+            selectors: selectors.iter().map(|selector| selector.to_string()).collect(),
+            ..TestPim::new(name)
+        }
+    }
+
+    fn roles() -> Vec<TestPim> {
+        vec![
+            role("Global Reader", Source::EntraRole, &["Global Reader", "f2ef992c"]),
+            role("readers (Member)", Source::Group, &["readers (Member)", "readers", "Reader"]),
+            role("prod (Reader)", Source::AzureRole, &["prod (Reader)", "prod", "Reader"]),
+        ]
+    }
+
+    fn resolved(selectors: &[&str], source: Option<Source>, pims: &[TestPim]) -> Result<Vec<String>, String> {
+        let pims = pims.iter().map(|pim| pim as &dyn Pim).collect::<Vec<_>>();
+        let selectors = selectors.iter().map(|selector| selector.to_string()).collect::<Vec<_>>();
+        resolve(&selectors, source, &pims).map(|selected| selected.iter().map(|pim| pim.name()).collect())
+    }
+
+    #[test]
+    fn matches_whole_selectors_ignoring_case() {
+        let roles = roles();
+        assert_eq!(resolved(&["global reader", "PROD"], None, &roles).unwrap(), ["Global Reader", "prod (Reader)"]);
+        assert_eq!(resolved(&["F2EF992C"], None, &roles).unwrap(), ["Global Reader"]);
+        assert!(resolved(&["Global"], None, &roles).is_err());
+    }
+
+    #[test]
+    fn only_matches_roles_of_the_source() {
+        let roles = roles();
+        assert_eq!(resolved(&["reader"], Some(Source::AzureRole), &roles).unwrap(), ["prod (Reader)"]);
+        assert!(resolved(&["Global Reader"], Some(Source::Group), &roles).is_err());
+    }
+
+    #[test]
+    fn lists_the_roles_an_ambiguous_selector_matches() {
+        let error = resolved(&["reader"], None, &roles()).unwrap_err();
+        assert_eq!(error, "'reader' is ambiguous, it matches:\n\treaders (Member)\n\tprod (Reader)");
+    }
+
+    #[test]
+    fn reports_every_selector_that_matches_no_role() {
+        let error = resolved(&["staging", "prod", "dev"], None, &roles()).unwrap_err();
+        assert_eq!(error, "No eligible role matches 'staging'\nNo eligible role matches 'dev'");
+    }
+
+    #[test]
+    fn selects_a_role_matched_by_several_selectors_once() {
+        let roles = roles();
+        assert_eq!(resolved(&["Global Reader", "f2ef992c", "global reader"], None, &roles).unwrap(), ["Global Reader"]);
+    }
+}
@@ -0,0 +1,172 @@
+use std::future::join;
+
+use futures::future::join_all;
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+use crate::azure::{graph, management, ReviewResult};
+use crate::cmd::pim::config::Config;
+use crate::cmd::pim::selection::parse_selection;
+use crate::cmd::pim::{graph_expiration, loaded, local_time, prompt, requested_duration, Source};
+
+/// A request from another user that is waiting for approval by the signed-in user
+struct PendingRequest {
+    source: Source,
+    approval_id: String,
+    requester: String,
+    role: String,
+    scope: String,
+    justification: String,
+    duration: String,
+    submitted: String,
+}
+
+impl PendingRequest {
+    fn from_group_request(request: graph::ScheduleRequest) -> Option<PendingRequest> {
+        let (duration, end) = graph_expiration(&request.schedule_info);
+        Some(PendingRequest {
+            source: Source::Group,
+            approval_id: request.approval_id?,
+            requester: graph_name(request.principal),
+            role: request.access_id.unwrap_or_default(),
+            scope: graph_name(request.group),
+            justification: request.justification.unwrap_or_default(),
+            duration: requested_duration(duration, end),
+            submitted: local_time(&request.created_date_time),
+        })
+    }
+
+    fn from_directory_role_request(request: graph::ScheduleRequest) -> Option<PendingRequest> {
+        let (duration, end) = graph_expiration(&request.schedule_info);
+        Some(PendingRequest {
+            source: Source::EntraRole,
+            approval_id: request.approval_id?,
+            requester: graph_name(request.principal),
+            role: graph_name(request.role_definition),
+            scope: match (request.directory_scope.and_then(|scope| scope.display_name), request.directory_scope_id.as_deref()) {
+                (Some(scope), _) => scope,
+                (None, None | Some("/")) => "Directory".to_owned(),
+                (None, Some(scope)) => scope.to_owned(),
+            },
+            justification: request.justification.unwrap_or_default(),
+            duration: requested_duration(duration, end),
+            submitted: local_time(&request.created_date_time),
+        })
+    }
+
+    fn from_role_request(request: management::ScheduleRequest) -> Option<PendingRequest> {
+        let properties = request.properties;
+        let expanded = properties.expanded_properties?;
+        let expiration = properties.schedule_info.and_then(|schedule| schedule.expiration);
+        let (duration, end) = expiration
+            .map(|expiration| (expiration.duration, expiration.end_date_time))
+            .unwrap_or_default();
+        Some(PendingRequest {
+            source: Source::AzureRole,
+            approval_id: properties.approval_id?,
+            requester: expanded.principal.map(|principal| principal.display_name).unwrap_or_default(),
+            role: expanded.role_definition.display_name,
+            scope: expanded.scope.display_name,
+            justification: properties.justification.unwrap_or_default(),
+            duration: requested_duration(duration, end),
+            submitted: properties.created_on.map(|created_on| local_time(&created_on)).unwrap_or_default(),
+        })
+    }
+
+    async fn review(
+        &self,
+        graph_client: &graph::GraphClient,
+        management_client: &management::ManagementClient,
+        review_result: ReviewResult,
+        justification: String,
+    ) -> Result<(), String> {
+        match self.source {
+            Source::Group => graph_client.review_group_request(&self.approval_id, review_result, justification).await,
+            Source::EntraRole => graph_client.review_directory_role_request(&self.approval_id, review_result, justification).await,
+            Source::AzureRole => management_client.review_request(&self.approval_id, review_result, justification).await,
+        }
+    }
+}
+
+fn graph_name(object: Option<graph::NamedObject>) -> String {
+    object.and_then(|object| object.display_name).unwrap_or_default()
+}
+
+pub async fn approvals(config: &Config) {
+    let graph_client = graph::GraphClient::new(&config.endpoints.graph);
+    let management_client = management::ManagementClient::new(&config.endpoints.management);
+
+    let (group_requests, directory_role_requests, role_requests) = join!(
+        graph_client.get_group_requests_to_approve(),
+        graph_client.get_directory_role_requests_to_approve(),
+        management_client.get_requests_to_approve()
+    ).await;
+    let mut errors = Vec::new();
+    let group_requests = loaded(Source::Group, group_requests, &mut errors);
+    let directory_role_requests = loaded(Source::EntraRole, directory_role_requests, &mut errors);
+    let role_requests = loaded(Source::AzureRole, role_requests, &mut errors);
+    errors.iter().for_each(|(source, error)| eprintln!("[{}] Failed to load requests waiting for your approval: {}", source, error));
+
+    let mut pending = group_requests.into_iter()
+        .filter_map(PendingRequest::from_group_request)
+        .chain(directory_role_requests.into_iter().filter_map(PendingRequest::from_directory_role_request))
+        .chain(role_requests.into_iter().filter_map(PendingRequest::from_role_request))
+        .collect::<Vec<PendingRequest>>();
+    if pending.is_empty() && !errors.is_empty() {
+        std::process::exit(1);
+    }
+
+    let reader = BufReader::new(tokio::io::stdin());
+    let mut lines = reader.lines();
+    while !pending.is_empty() {
+        pending.iter().enumerate().for_each(|(index, request)| {
+            println!("{}.\t[{}] {} requests {} on {}", index + 1, request.source, request.requester, request.role, request.scope);
+            println!("\tDuration: {}, submitted {}", request.duration, request.submitted);
+            println!("\tJustification: {}", request.justification);
+        });
+
+        let selection = prompt(&mut lines, "Select request(s) separated by ',' or 'all', leave empty to quit: ").await;
+        if selection.is_empty() {
+            return;
+        }
+        let selected = if selection == "all" {
+            (0..pending.len()).collect::<Vec<usize>>()
+        } else {
+            match parse_selection(&selection, pending.len()) {
+                Ok(indexes) => indexes,
+                Err(error) => {
+                    println!("{}", error);
+                    continue;
+                }
+            }
+        };
+
+        let review_result = loop {
+            match prompt(&mut lines, "Approve or deny? [a/d]: ").await.as_str() {
+                "a" => break ReviewResult::Approve,
+                "d" => break ReviewResult::Deny,
+                _ => println!("Answer `a` to approve or `d` to deny the selected requests"),
+            }
+        };
+        let justification = prompt(&mut lines, "Justification: ").await;
+
+        let results = join_all(selected.iter().map(|index| {
+            pending[*index].review(&graph_client, &management_client, review_result, justification.clone())
+        })).await;
+
+        let mut reviewed = Vec::with_capacity(selected.len());
+        selected.into_iter().zip(results).for_each(|(index, result)| {
+            let request = &pending[index];
+            match result {
+                Ok(()) => {
+                    println!("Reviewed request from {} for {} on {}", request.requester, request.role, request.scope);
+                    reviewed.push(index);
+                }
+                Err(error) => println!("Failed to review request from {} for {}: {}", request.requester, request.role, error),
+            }
+        });
+        reviewed.into_iter().rev().for_each(|index| {
+            pending.remove(index);
+        });
+    }
+    println!("No requests are waiting for your approval");
+}
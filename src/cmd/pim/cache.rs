@@ -1,30 +1,39 @@
 use std::fs::File;
 use std::future::Future;
 use std::path::PathBuf;
-use home::home_dir;
+use chrono::{DateTime, Duration, Utc};
 use serde::Serialize;
 use serde::de::DeserializeOwned;
 use crate::cmd::pim::aad_roles::AadRoleInfo;
+use crate::cmd::pim::config::config_dir;
 use crate::cmd::pim::group::GroupInfo;
 use crate::cmd::pim::role::RoleInfo;
+use crate::cmd::pim::Source;
 
 pub struct Cache {
     pub refresh: bool,
+    // Caches older than this are fetched again, caches are kept until refreshed if not set
+    pub ttl: Option<Duration>,
 }
 
 impl Cache {
     fn get_cache_dir() -> PathBuf {
-        home_dir().unwrap().join(".config").join("pimple").join("cache")
+        config_dir().join("cache")
     }
 
     fn get_cache_file(cache_type: &str) -> PathBuf {
         Cache::get_cache_dir().join(cache_type)
     }
 
-    fn get_cache<T: DeserializeOwned>(cache_type: &str) -> Option<T> {
+    /// Caches written by older versions of pimple that can not be read, or older than the TTL, are treated as missing
+    fn get_cache<T: DeserializeOwned>(&self, cache_type: &str) -> Option<T> {
         let cache_file = Cache::get_cache_file(cache_type);
-        return if cache_file.exists() {
-            serde_json::from_reader(File::open(cache_file).unwrap()).unwrap()
+        let expired = self.ttl.is_some_and(|ttl| {
+            let modified = cache_file.metadata().and_then(|metadata| metadata.modified()).ok();
+            modified.is_none_or(|modified| DateTime::<Utc>::from(modified) + ttl < Utc::now())
+        });
+        return if cache_file.exists() && !expired {
+            serde_json::from_reader(File::open(cache_file).unwrap()).ok()
         } else {
             None
         };
@@ -44,39 +53,51 @@ impl Cache {
         serde_json::to_writer(cache_file, data).unwrap()
     }
 
+    /// Reads the cache, or fetches and caches the data if it is missing or a refresh is requested.
+    /// Failed fetches are not cached
     async fn fetch_cache<Fut, O: DeserializeOwned + Serialize>(
         &self,
         default: impl FnOnce() -> Fut,
         path: &str,
-    ) -> O where Fut: Future<Output=O> {
+    ) -> Result<O, String> where Fut: Future<Output=Result<O, String>> {
         if !self.refresh {
-            if let Some(cache) = Cache::get_cache(path) {
-                return cache;
+            if let Some(cache) = self.get_cache(path) {
+                return Ok(cache);
             }
         }
-        let data = default().await;
+        let data = default().await?;
         Cache::save_cache(path, &data);
-        data
+        Ok(data)
+    }
+
+    /// Removes the cached eligible roles of a source, so they are fetched again on the next run
+    pub fn invalidate(source: Source) {
+        let cache_type = match source {
+            Source::Group => "groups.json",
+            Source::AzureRole => "roles.json",
+            Source::EntraRole => "aad_roles.json",
+        };
+        let _ = std::fs::remove_file(Cache::get_cache_file(cache_type));
     }
 
     pub async fn fetch_group_cache<Fut>(
         &self,
         default: impl FnOnce() -> Fut,
-    ) -> Vec<GroupInfo> where Fut: Future<Output=Vec<GroupInfo>> {
+    ) -> Result<Vec<GroupInfo>, String> where Fut: Future<Output=Result<Vec<GroupInfo>, String>> {
         self.fetch_cache(default, "groups.json").await
     }
 
     pub async fn fetch_role_info_cache<Fut>(
         &self,
         default: impl FnOnce() -> Fut,
-    ) -> Vec<RoleInfo> where Fut: Future<Output=Vec<RoleInfo>> {
+    ) -> Result<Vec<RoleInfo>, String> where Fut: Future<Output=Result<Vec<RoleInfo>, String>> {
         self.fetch_cache(default, "roles.json").await
     }
 
     pub async fn fetch_aad_role_cache<Fut>(
         &self,
         default: impl FnOnce() -> Fut,
-    ) -> Vec<AadRoleInfo> where Fut: Future<Output=Vec<AadRoleInfo>> {
+    ) -> Result<Vec<AadRoleInfo>, String> where Fut: Future<Output=Result<Vec<AadRoleInfo>, String>> {
         self.fetch_cache(default, "aad_roles.json").await
     }
 }
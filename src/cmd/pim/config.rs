@@ -0,0 +1,326 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use chrono::{Duration, Local};
+use clap::Args;
+use home::home_dir;
+use serde::{Deserialize, Serialize};
+
+use crate::azure::graph::MS_GRAPH_URL;
+use crate::azure::management::MANAGEMENT_URL;
+use crate::cmd::pim::policy::RequestedDuration;
+use crate::cmd::pim::schedule::parse_duration;
+use crate::cmd::pim::{GroupBy, SortBy, Source};
+
+// Settings overriding the configuration file, from the command line or the environment
+#[derive(Args)]
+pub struct ConfigArgs {
+    #[arg(long, global = true, env = "PIMPLE_CONFIG", help = "Configuration file to use instead of config.toml in the pimple config directory")]
+    config: Option<PathBuf>,
+    #[arg(long, global = true, env = "PIMPLE_DURATION", help = "Duration used when none is given, like `1h` or `max`")]
+    default_duration: Option<String>,
+    #[arg(long, global = true, env = "PIMPLE_REASON", help = "Reason used when none is given, `{date}` is replaced with the current date")]
+    default_reason: Option<String>,
+    #[arg(long, global = true, value_enum, value_delimiter = ',', env = "PIMPLE_SOURCES", help = "Sources to load eligible roles from, separated by ','")]
+    sources: Option<Vec<Source>>,
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        env = "PIMPLE_GROUP_BY",
+        help = "Group the menu by role, show Azure roles in a tree of their scopes, or list roles without grouping"
+    )]
+    group_by: Option<GroupBy>,
+    #[arg(long, global = true, value_enum, env = "PIMPLE_SORT_BY", help = "Sort roles within each group by name, or by when their eligibility ends")]
+    sort_by: Option<SortBy>,
+    #[arg(long, global = true, env = "PIMPLE_CACHE_TTL", help = "Fetch eligible roles again when the cache is older than this, like `24h`")]
+    cache_ttl: Option<String>,
+    #[arg(long, global = true, env = "PIMPLE_EXPIRY_WARNING_DAYS", help = "Warn when an eligibility ends within this number of days")]
+    expiry_warning_days: Option<i64>,
+    #[arg(long, global = true, env = "PIMPLE_GRAPH_URL", help = "Base URL of Microsoft Graph")]
+    graph_url: Option<String>,
+    #[arg(long, global = true, env = "PIMPLE_MANAGEMENT_URL", help = "Base URL of Azure Resource Manager")]
+    management_url: Option<String>,
+}
+
+/// Defaults read from `config.toml`, with the settings given on the command line or in the environment applied
+#[derive(Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    // Like `1h` or `max`, the maximum of each role if not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    pub sources: Vec<Source>,
+    pub group_by: GroupBy,
+    pub sort_by: SortBy,
+    // The cache is used until it is refreshed if not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache_ttl: Option<String>,
+    pub expiry_warning_days: i64,
+    pub endpoints: Endpoints,
+    // Bundles of roles activated together with `pimple pim --profile <name>`
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, Profile>,
+    // Roles required by the project in the working directory, from the nearest `.pimple.toml`
+    #[serde(skip)]
+    pub project: Option<Project>,
+    // The file the configuration was read from, None when it does not exist
+    #[serde(skip)]
+    pub file: Option<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Endpoints {
+    pub graph: String,
+    pub management: String,
+}
+
+/// Roles activated together, with the duration and reason used instead of the defaults
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    // Selectors like those of `pimple pim activate --role`
+    pub roles: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+/// A `.pimple.toml` checked into a repository, declaring the roles working in it requires
+pub struct Project {
+    pub file: PathBuf,
+    pub profile: Profile,
+}
+
+impl Profile {
+    pub fn duration(&self) -> Option<RequestedDuration> {
+        self.duration.as_deref().and_then(|duration| RequestedDuration::parse(duration).ok())
+    }
+
+    pub fn reason(&self) -> Option<String> {
+        self.reason.as_deref().map(expand_reason)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.roles.is_empty() {
+            return Err("has no roles".to_owned());
+        }
+        if let Some(duration) = &self.duration {
+            RequestedDuration::parse(duration).map_err(|error| format!("has an invalid duration: {}", error))?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            duration: None,
+            reason: None,
+            sources: vec![Source::Group, Source::AzureRole, Source::EntraRole],
+            group_by: GroupBy::Role,
+            sort_by: SortBy::Name,
+            cache_ttl: None,
+            expiry_warning_days: 14,
+            endpoints: Endpoints::default(),
+            profiles: BTreeMap::new(),
+            project: None,
+            file: None,
+        }
+    }
+}
+
+impl Default for Endpoints {
+    fn default() -> Endpoints {
+        Endpoints {
+            graph: MS_GRAPH_URL.to_owned(),
+            management: MANAGEMENT_URL.to_owned(),
+        }
+    }
+}
+
+impl Config {
+    pub fn load(args: &ConfigArgs) -> Result<Config, String> {
+        let path = args.config.clone().unwrap_or_else(|| config_dir().join("config.toml"));
+        let config = if path.exists() {
+            let content = std::fs::read_to_string(&path).map_err(|error| format!("Failed to read {}: {}", path.display(), error))?;
+            Config {
+                file: Some(path.clone()),
+                ..toml::from_str(&content).map_err(|error| format!("Invalid configuration in {}: {}", path.display(), error))?
+            }
+        } else if args.config.is_some() {
+            return Err(format!("The configuration file {} does not exist", path.display()));
+        } else {
+            Config::default()
+        };
+        Config::apply(config, find_project(), args)
+    }
+
+    /// Applies the project and the settings from the environment and command line to the configuration file
+    fn apply(mut config: Config, project: Option<Project>, args: &ConfigArgs) -> Result<Config, String> {
+        // The project overrides the configuration file, but not the environment or the command line
+        config.project = project;
+        if let Some(project) = &config.project {
+            config.duration = project.profile.duration.clone().or(config.duration);
+            config.reason = project.profile.reason.clone().or(config.reason);
+        }
+
+        config.duration = args.default_duration.clone().or(config.duration);
+        config.reason = args.default_reason.clone().or(config.reason);
+        config.sources = args.sources.clone().unwrap_or(config.sources);
+        config.group_by = args.group_by.unwrap_or(config.group_by);
+        config.sort_by = args.sort_by.unwrap_or(config.sort_by);
+        config.cache_ttl = args.cache_ttl.clone().or(config.cache_ttl);
+        config.expiry_warning_days = args.expiry_warning_days.unwrap_or(config.expiry_warning_days);
+        config.endpoints.graph = args.graph_url.clone().unwrap_or(config.endpoints.graph);
+        config.endpoints.management = args.management_url.clone().unwrap_or(config.endpoints.management);
+
+        if let Some(duration) = &config.duration {
+            RequestedDuration::parse(duration).map_err(|error| format!("Invalid duration: {}", error))?;
+        }
+        if let Some(cache_ttl) = &config.cache_ttl {
+            parse_duration(cache_ttl).ok_or_else(|| format!("Invalid cache TTL '{}', example `12h` or `7d`", cache_ttl))?;
+        }
+        for (name, profile) in &config.profiles {
+            profile.validate().map_err(|error| format!("The profile '{}' {}", name, error))?;
+        }
+        Ok(config)
+    }
+
+    /// Duration used when none is given, the maximum of each role unless configured
+    pub fn duration(&self) -> RequestedDuration {
+        self.duration.as_deref()
+            .and_then(|duration| RequestedDuration::parse(duration).ok())
+            .unwrap_or(RequestedDuration::Maximum)
+    }
+
+    /// Reason used when none is given, with `{date}` replaced by the current date
+    pub fn reason(&self) -> Option<String> {
+        self.reason.as_deref().map(expand_reason)
+    }
+
+    pub fn cache_ttl(&self) -> Option<Duration> {
+        self.cache_ttl.as_deref().and_then(parse_duration)
+    }
+}
+
+/// The nearest `.pimple.toml` in the working directory or any of its parents
+/// A project that can not be read is ignored with a warning, so it does not keep pimple from being used in the directory
+fn find_project() -> Option<Project> {
+    let dir = std::env::current_dir().ok()?;
+    let file = dir.ancestors().map(|dir| dir.join(".pimple.toml")).find(|file| file.is_file())?;
+    match read_project(&file) {
+        Ok(profile) => Some(Project { file, profile }),
+        Err(error) => {
+            eprintln!("Warning: ignoring the project in {}: {}", file.display(), error);
+            None
+        }
+    }
+}
+
+fn read_project(file: &Path) -> Result<Profile, String> {
+    let content = std::fs::read_to_string(file).map_err(|error| error.to_string())?;
+    let profile = toml::from_str::<Profile>(&content).map_err(|error| error.to_string())?;
+    profile.validate().map_err(|error| format!("the project {}", error))?;
+    Ok(profile)
+}
+
+/// Replaces `{date}` in a reason with the current date
+fn expand_reason(reason: &str) -> String {
+    reason.replace("{date}", &Local::now().format("%Y-%m-%d").to_string())
+}
+
+/// `pimple` in `XDG_CONFIG_HOME`, or in `~/.config` when it is not set
+pub fn config_dir() -> PathBuf {
+    std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(|| home_dir().unwrap().join(".config"))
+        .join("pimple")
+}
+
+/// Prints the configuration in effect, as it would be written in `config.toml`, or why it could not be loaded
+pub fn config(args: &ConfigArgs) {
+    let config = match Config::load(args) {
+        Ok(config) => config,
+        Err(error) => {
+            eprintln!("The configuration could not be loaded: {}", error);
+            std::process::exit(1);
+        }
+    };
+    match &config.file {
+        Some(file) => println!("# Read from {}", file.display()),
+        None => println!("# {} does not exist, showing the defaults", config_dir().join("config.toml").display()),
+    }
+    print!("{}", toml::to_string_pretty(&config).unwrap());
+    if let Some(project) = &config.project {
+        println!("\n# Required by this project, read from {}", project.file.display());
+        print!("{}", toml::to_string_pretty(&project.profile).unwrap());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use clap::Parser;
+
+    use crate::cmd::pim::SortBy;
+
+    use super::{Config, ConfigArgs, Project};
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        config: ConfigArgs,
+    }
+
+    fn args(args: &[&str]) -> ConfigArgs {
+        Cli::parse_from(["pimple"].iter().chain(args)).config
+    }
+
+    fn file() -> Config {
+        toml::from_str("duration = \"1h\"\nreason = \"file\"\nsort_by = \"expiry\"\n").unwrap()
+    }
+
+    fn project() -> Option<Project> {
+        Some(Project {
+            file: PathBuf::from(".pimple.toml"),
+            profile: toml::from_str("roles = [\"prod (Owner)\"]\nduration = \"2h\"\n").unwrap(),
+        })
+    }
+
+    // The only test setting the environment, so other tests are not affected by it
+    #[test]
+    fn applies_the_project_environment_and_command_line_in_order() {
+        std::env::remove_var("PIMPLE_DURATION");
+        let config = Config::apply(file(), None, &args(&[])).unwrap();
+        assert_eq!(config.duration.as_deref(), Some("1h"));
+
+        let config = Config::apply(file(), project(), &args(&[])).unwrap();
+        assert_eq!(config.duration.as_deref(), Some("2h"));
+        assert_eq!(config.reason.as_deref(), Some("file"));
+        assert!(config.sort_by == SortBy::Expiry);
+
+        std::env::set_var("PIMPLE_DURATION", "3h");
+        let config = Config::apply(file(), project(), &args(&[])).unwrap();
+        assert_eq!(config.duration.as_deref(), Some("3h"));
+
+        let config = Config::apply(file(), project(), &args(&["--default-duration", "4h", "--sort-by", "name"])).unwrap();
+        std::env::remove_var("PIMPLE_DURATION");
+        assert_eq!(config.duration.as_deref(), Some("4h"));
+        assert!(config.sort_by == SortBy::Name);
+    }
+
+    #[test]
+    fn rejects_invalid_settings() {
+        assert!(Config::apply(Config::default(), None, &args(&["--cache-ttl", "soon"])).is_err());
+        let config = toml::from_str("[profiles.morning]\nroles = []\n").unwrap();
+        assert!(Config::apply(config, None, &args(&[])).is_err());
+        assert!(toml::from_str::<Config>("durration = \"1h\"\n").is_err());
+    }
+}
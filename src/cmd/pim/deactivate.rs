@@ -0,0 +1,48 @@
+use clap::Args;
+use futures::future::join_all;
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+use crate::cmd::pim::cache::Cache;
+use crate::cmd::pim::config::Config;
+use crate::cmd::pim::{select_active, Clients, Pim, Pims};
+
+#[derive(Args)]
+pub struct DeactivateArgs {
+    #[arg(short, long, help = "Fetch updated cache roles from Azure")]
+    refresh: bool,
+    #[arg(short, long, help = "Deactivate every active role without prompting")]
+    all: bool,
+}
+
+pub async fn deactivate(args: &DeactivateArgs, config: &Config) {
+    let cache = Cache {
+        refresh: args.refresh,
+        ttl: config.cache_ttl(),
+    };
+    let clients = Clients::new(config);
+    let pims = Pims::load(&clients, &cache, config, true).await;
+    pims.report_errors();
+    pims.warn_expiring(config.expiry_warning_days);
+    let active = pims.all()
+        .into_iter()
+        .filter(|pim| pim.active().is_some())
+        .collect::<Vec<&dyn Pim>>();
+
+    if active.is_empty() {
+        pims.no_active_roles("You have no active roles");
+        return;
+    }
+
+    let selected = if args.all {
+        active
+    } else {
+        let reader = BufReader::new(tokio::io::stdin());
+        let mut lines = reader.lines();
+        select_active(&mut lines, &active, "Please select role(s) to deactivate separated by ',': ").await
+    };
+
+    let requests = join_all(selected.into_iter().map(|pim| pim.deactivate())).await;
+    if requests.iter().any(|request_id| request_id.is_none()) {
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,76 @@
+use clap::Args;
+use futures::future::join_all;
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+use crate::azure::Activation;
+use crate::cmd::pim::cache::Cache;
+use crate::cmd::pim::config::Config;
+use crate::cmd::pim::{activate_all, durations, policy, prompt, select_active, ticket_or_prompt, Clients, Pim, Pims};
+
+#[derive(Args)]
+pub struct ExtendArgs {
+    #[arg(short, long, help = "Fetch updated cache roles from Azure")]
+    refresh: bool,
+}
+
+/// PIM does not let users extend their own activations, so an extension is requested
+/// as a new activation starting when the current one ends
+pub async fn extend(args: &ExtendArgs, config: &Config) {
+    let cache = Cache {
+        refresh: args.refresh,
+        ttl: config.cache_ttl(),
+    };
+    let clients = Clients::new(config);
+    let pims = Pims::load(&clients, &cache, config, true).await;
+    pims.report_errors();
+    pims.warn_expiring(config.expiry_warning_days);
+    let active = pims.all()
+        .into_iter()
+        .filter(|pim| pim.active().is_some_and(|active| active.end.is_some()))
+        .collect::<Vec<&dyn Pim>>();
+
+    if active.is_empty() {
+        pims.no_active_roles("You have no active roles that expire");
+        return;
+    }
+
+    let reader = BufReader::new(tokio::io::stdin());
+    let mut lines = reader.lines();
+    let selected = select_active(&mut lines, &active, "Please select role(s) to extend separated by ',': ").await;
+    let policies = policy::policies(&selected).await;
+    let durations = durations(&mut lines, &selected, &policies, config, "Select a duration to extend with, example `30m`, `1h30m`, `PT1H` or `max`").await;
+    let reason = prompt(&mut lines, "Reason (leave empty to reuse the current reason): ").await;
+
+    let mut reasons = if reason.is_empty() {
+        join_all(selected.iter().map(|pim| pim.last_reason())).await
+    } else {
+        vec![Some(reason); selected.len()]
+    };
+    if reasons.iter().any(|reason| reason.is_none()) {
+        let fallback = prompt(&mut lines, "No previous reason was found for some roles, reason: ").await;
+        reasons.iter_mut()
+            .filter(|reason| reason.is_none())
+            .for_each(|reason| *reason = Some(fallback.clone()));
+    }
+
+    let ticket = ticket_or_prompt(&mut lines, None, &policies).await;
+
+    let requests = selected.into_iter()
+        .zip(reasons)
+        .zip(durations)
+        .map(|((pim, reason), duration)| (pim, Activation {
+            reason: reason.unwrap_or_default(),
+            duration,
+            start: pim.active().and_then(|active| active.end),
+            ticket: ticket.clone(),
+        }))
+        .collect::<Vec<_>>();
+    if let Err(violations) = policy::check(&requests, &policies) {
+        eprintln!("{}", violations);
+        std::process::exit(1);
+    }
+    let requests = activate_all(requests).await;
+    if requests.iter().any(|(_, request_id)| request_id.is_none()) {
+        std::process::exit(1);
+    }
+}
@@ -1,100 +1,242 @@
+use std::fmt::{Display, Formatter};
 use std::future::Future;
 use std::pin::Pin;
-use crate::azure::{graph, pim};
-use crate::cmd::pim::Pim;
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use crate::azure::{graph, Activation};
+use crate::cmd::pim::{parse_time, submitted, ActiveAssignment, ActiveAssignments, Pim, Source};
+use crate::cmd::pim::policy::{attach_policies, Policy};
 use serde::{Serialize, Deserialize};
 
-pub async fn fetch_group_info(pim_client: &pim::PimClient, graph_client: &graph::GraphClient) -> Vec<GroupInfo> {
-    let groups = pim_client.fetch_group_pim().await;
-    if groups.is_empty() {
-        return vec![];
-    }
-
-    let group_query = create_group_query(&groups);
-    let extra_group_info = graph_client.get_groups(&group_query).await;
-    return groups.into_iter()
-        .map(|group| {
-            GroupInfo {
+pub async fn fetch_group_info(graph_client: &graph::GraphClient) -> Result<Vec<GroupInfo>, String> {
+    let group_info = graph_client.get_eligible_groups().await?
+        .into_iter()
+        .filter_map(|group| {
+            let (group_name, group_description) = group.group
+                .map(|g| (g.display_name.unwrap_or_default(), g.description))
+                .unwrap_or_default();
+            let Some(access) = Access::from_id(&group.access_id) else {
+                eprintln!("Warning: skipping the eligibility for {} with the unknown access '{}'", group_name, group.access_id);
+                return None;
+            };
+            Some(GroupInfo {
                 role_assignment_id: group.id,
-                group_description: (&extra_group_info).into_iter()
-                    .find(|g| g.id == group.resourceId)
-                    .map(|g| g.description.clone().unwrap_or_else(|| g.display_name.clone())),
-                group_object_id: group.resourceId,
-                role_definition_id: group.roleDefinitionId,
-                role_definition_name: group.roleDefinition.displayName,
-                group_name: group.roleDefinition.resource.displayName,
-            }
+                group_object_id: group.group_id,
+                access,
+                group_description: group_description.or_else(|| Some(group_name.clone())),
+                group_name,
+                eligibility_end: group.end_date_time,
+                policy: None,
+            })
         })
-        .collect();
+        .collect::<Vec<GroupInfo>>();
+
+    Ok(attach_policies(
+        group_info,
+        |info| fetch_policy(graph_client, info.group_object_id.to_owned(), info.access.id()),
+        |info, policy| GroupInfo { policy, ..info },
+    ).await)
+}
+
+async fn fetch_policy(graph_client: &graph::GraphClient, group_id: String, access_id: &str) -> Result<Policy, String> {
+    Ok(Policy::from_rules(&graph_client.get_policy_rules(&group_id, "Group", access_id).await?))
+}
+
+pub async fn fetch_active_assignments(graph_client: &graph::GraphClient) -> Result<ActiveAssignments, String> {
+    Ok(graph_client.get_active_groups().await?
+        .into_iter()
+        .filter(|group| group.assignment_type.as_deref() == Some("activated"))
+        .map(|group| (
+            (group.group_id, group.access_id),
+            ActiveAssignment::new(group.start_date_time, group.end_date_time),
+        ))
+        .collect())
+}
+
+/// Whether an eligibility grants membership of a group or ownership, which lets the owner manage its members
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Access {
+    Member,
+    Owner,
+}
+
+impl Access {
+    fn from_id(access_id: &str) -> Option<Access> {
+        match access_id {
+            "member" => Some(Access::Member),
+            "owner" => Some(Access::Owner),
+            _ => None,
+        }
+    }
+
+    /// Id of the access in Microsoft Graph
+    fn id(&self) -> &'static str {
+        match self {
+            Access::Member => "member",
+            Access::Owner => "owner",
+        }
+    }
 }
 
-fn create_group_query(groups: &Vec<pim::AssignableGroup>) -> String {
-    let string_size = groups.len() * (36 + 11); // length of UUID + ", "
-    let group_ids = (&groups).into_iter()
-        .fold(String::with_capacity(string_size), |mut result, v| {
-            result.push_str("or id eq '");
-            result.push_str(&v.resourceId);
-            result.push_str("'");
-            result
-        });
-    let group_query = format!("$filter={}", &group_ids[3..]);
-    group_query
+impl Display for Access {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Access::Member => write!(f, "Member"),
+            Access::Owner => write!(f, "Owner"),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct GroupInfo {
     pub role_assignment_id: String,
     pub group_object_id: String,
-    pub role_definition_id: String,
-    pub role_definition_name: String,
+    pub access: Access,
     pub group_name: String,
     pub group_description: Option<String>,
+    #[serde(default)]
+    pub eligibility_end: Option<String>,
+    #[serde(default)]
+    pub policy: Option<Policy>,
+}
+
+impl GroupInfo {
+    pub fn assignment_key(&self) -> (String, String) {
+        (self.group_object_id.to_owned(), self.access.id().to_owned())
+    }
 }
 
 pub struct GroupPim<'a> {
-    pim_client: &'a pim::PimClient,
+    graph_client: &'a graph::GraphClient,
     group_info: GroupInfo,
+    active: Option<ActiveAssignment>,
 }
 
 impl<'a> GroupPim<'a> {
-    pub fn new(pim_client: &'a pim::PimClient, group_info: GroupInfo) -> GroupPim<'a> {
+    pub fn new(graph_client: &'a graph::GraphClient, group_info: GroupInfo, active: Option<ActiveAssignment>) -> GroupPim<'a> {
         GroupPim {
-            pim_client,
+            graph_client,
             group_info,
+            active,
         }
     }
+
+    pub fn access(&self) -> Access {
+        self.group_info.access
+    }
 }
 
 impl<'a> Pim<'a> for GroupPim<'a> {
+    fn source(&self) -> Source {
+        Source::Group
+    }
+
     fn group_by(&self) -> String {
         self.group_info.group_description.clone().unwrap_or(self.group_info.group_name.to_owned())
     }
 
     fn resource_id(&self) -> String {
-        format!("{} ({})", self.group_info.group_name, self.group_info.role_definition_name)
+        format!("{} ({})", self.group_info.group_name, self.group_info.access)
     }
 
-    fn activate(&self, reason: String, duration: String) -> Pin<Box<dyn Future<Output = ()> + 'a>> {
+    fn name(&self) -> String {
+        self.resource_id()
+    }
 
+    fn selectors(&self) -> Vec<String> {
+        vec![
+            self.name(),
+            self.group_info.group_name.to_owned(),
+            self.group_info.group_object_id.to_owned(),
+            self.group_info.role_assignment_id.to_owned(),
+        ]
+    }
+
+    fn activate(&self, activation: Activation) -> Pin<Box<dyn Future<Output = Option<String>> + 'a>> {
+        let graph_client = self.graph_client;
+        let name = self.name();
+        let group_id = self.group_info.group_object_id.to_owned();
+        let access_id = self.group_info.access.id().to_owned();
+        Box::pin(async move {
+            let result = graph_client.request_group(activation, group_id, access_id).await;
+            submitted(name, result.map(|request| (request.id, request.status)))
+        })
+    }
+
+    fn request_status(&self, request_id: String) -> Pin<Box<dyn Future<Output = Result<String, String>> + 'a>> {
+        let graph_client = self.graph_client;
+        Box::pin(async move {
+            graph_client.get_group_request(&request_id).await.map(|request| request.status)
+        })
+    }
+
+    fn active(&self) -> Option<&ActiveAssignment> {
+        self.active.as_ref()
+    }
+
+    fn deactivate(&self) -> Pin<Box<dyn Future<Output = Option<String>> + 'a>> {
+        let graph_client = self.graph_client;
+        let name = self.name();
         let group_id = self.group_info.group_object_id.to_owned();
-        let role_assignment_id = self.group_info.role_assignment_id.to_owned();
-        let role_definition_id = self.group_info.role_definition_id.to_owned();
-        Box::pin(async {
-            println!(
-                "{}",
-                self.pim_client.request_group_access(
-                    reason,
-                    duration,
-                    group_id,
-                    role_assignment_id,
-                    role_definition_id,
-                ).await
-            )
+        let access_id = self.group_info.access.id().to_owned();
+        Box::pin(async move {
+            let result = graph_client.request_group_deactivation(group_id, access_id).await;
+            submitted(name, result.map(|request| (request.id, request.status)))
+        })
+    }
+
+    fn policy(&self) -> Pin<Box<dyn Future<Output = Result<Policy, String>> + 'a>> {
+        let graph_client = self.graph_client;
+        let cached = self.group_info.policy.clone();
+        let group_id = self.group_info.group_object_id.to_owned();
+        let access_id = self.group_info.access.id();
+        Box::pin(async move {
+            match cached {
+                Some(policy) => Ok(policy),
+                None => fetch_policy(graph_client, group_id, access_id).await,
+            }
+        })
+    }
+
+    fn cached_policy(&self) -> Option<&Policy> {
+        self.group_info.policy.as_ref()
+    }
+
+    fn last_reason(&self) -> Pin<Box<dyn Future<Output = Option<String>> + 'a>> {
+        let graph_client = self.graph_client;
+        let name = self.name();
+        let group_id = self.group_info.group_object_id.to_owned();
+        let access_id = self.group_info.access.id().to_owned();
+        Box::pin(async move {
+            graph_client.get_group_requests(&group_id, &access_id).await
+                .map_err(|error| eprintln!("Warning: failed to read the previous requests for {}: {}", name, error))
+                .ok()?
+                .into_iter()
+                .filter(|request| request.action.as_deref() == Some("selfActivate"))
+                .max_by(|a, b| a.created_date_time.cmp(&b.created_date_time))
+                .and_then(|request| request.justification)
         })
     }
 
 
     fn ensure_token(&self) -> Pin<Box<dyn Future<Output = ()> + 'a>> {
-        Box::pin(self.pim_client.ensure_token())
+        Box::pin(self.graph_client.ensure_token())
+    }
+
+    fn eligibility_end(&self) -> Option<DateTime<Utc>> {
+        self.group_info.eligibility_end.as_deref().and_then(parse_time)
+    }
+
+    fn renew(&self, activation: Activation) -> Pin<Box<dyn Future<Output = Option<String>> + 'a>> {
+        let graph_client = self.graph_client;
+        let name = self.name();
+        let group_id = self.group_info.group_object_id.to_owned();
+        let access_id = self.group_info.access.id().to_owned();
+        let expired = self.eligibility_end().is_some_and(|end| end <= Utc::now());
+        Box::pin(async move {
+            let result = graph_client.request_group_renewal(activation, group_id, access_id, expired).await;
+            submitted(name, result.map(|request| (request.id, request.status)))
+        })
     }
 }
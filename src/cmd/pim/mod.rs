@@ -1,119 +1,941 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
 use std::future::{Future, join};
-use std::io::Write;
+use std::io::{IsTerminal, Write};
 use std::pin::Pin;
+use std::time::Duration;
 
-use clap::Args;
+use chrono::{DateTime, Local, Utc};
+
+use clap::{Args, Subcommand, ValueEnum};
+use serde::{Deserialize, Serialize};
 use futures::future::join_all;
 use tokio::io::{AsyncBufRead, AsyncBufReadExt, BufReader, Lines};
 
-use crate::azure::{graph, management, pim};
+use crate::azure::{graph, management, Activation, Ticket};
 use crate::cmd::pim::aad_roles::AadRolePim;
 use crate::cmd::pim::cache::Cache;
-use crate::cmd::pim::group::GroupPim;
+use crate::cmd::pim::config::Config;
+use crate::cmd::pim::group::{Access, GroupPim};
+use crate::cmd::pim::policy::{Policy, RequestedDuration};
 use crate::cmd::pim::role::RolePim;
 
+pub mod approvals;
+pub mod config;
+pub mod deactivate;
+pub mod extend;
+pub mod renew;
+pub mod requests;
+pub mod status;
 mod aad_roles;
+mod activate;
 mod cache;
 mod group;
+mod picker;
+mod policy;
 mod role;
+mod schedule;
+mod selection;
+mod wait;
+#[cfg(test)]
+mod test_pim;
 
 #[derive(Args)]
 pub struct PimArgs {
-    #[arg(short, long, help = "Fetch updated cache roles from Azure")]
+    #[arg(short, long, global = true, help = "Fetch updated cache roles from Azure")]
     refresh: bool,
+    #[arg(short, long, global = true, help = "Wait until the activated roles are provisioned")]
+    wait: bool,
+    #[arg(long, global = true, default_value_t = 300, help = "Seconds to wait for activation when using --wait")]
+    wait_timeout: u64,
+    #[arg(
+        long,
+        global = true,
+        value_parser = schedule::parse_start,
+        help = "Start the activation later, example `+2h`, `02:00` or `2023-06-01 02:00`"
+    )]
+    start: Option<DateTime<Utc>>,
+    #[arg(long, global = true, value_enum, help = "Only show groups eligible with this access")]
+    access: Option<Access>,
+    #[arg(long, help = "Activate the roles of a profile in the configuration file without prompting")]
+    profile: Option<String>,
+    #[arg(long, conflicts_with = "profile", help = "Activate the roles required by the project, from the nearest .pimple.toml, without prompting")]
+    project: bool,
+    #[arg(long, global = true, help = "Select roles by their number instead of in the full-screen picker")]
+    no_picker: bool,
+    #[arg(long, global = true, help = "Number of the change ticket the activation is for")]
+    ticket_number: Option<String>,
+    #[arg(long, global = true, requires = "ticket_number", help = "System the change ticket is tracked in")]
+    ticket_system: Option<String>,
+    #[command(subcommand)]
+    command: Option<PimCommands>,
 }
 
-pub async fn pim(args: &PimArgs) {
-    let cache = Cache {
-        refresh: args.refresh
-    };
+impl PimArgs {
+    fn ticket(&self) -> Option<Ticket> {
+        self.ticket_number.as_ref().map(|number| Ticket {
+            number: number.to_owned(),
+            system: self.ticket_system.clone().unwrap_or_default(),
+        })
+    }
+}
 
-    let pim_client = pim::PimClient::new();
-    let graph_client = graph::GraphClient::new();
-    let management_client = management::ManagementClient::new();
+#[derive(Subcommand)]
+enum PimCommands {
+    /// Activate roles without prompting, for use in scripts
+    Activate(activate::ActivateArgs),
+}
 
-    let (group_pim_info, role_pim_info, aad_pim_info) = join!(
-        cache.fetch_group_cache(async || { group::fetch_group_info(&pim_client, &graph_client).await }),
-        cache.fetch_role_info_cache(async || { role::fetch_role_info(&management_client).await }),
-        cache.fetch_aad_role_cache(async || { aad_roles::fetch_aad_role_info(&pim_client).await })
-    ).await;
+/// How roles are grouped in the menu, only Azure roles can be shown in a tree of their scopes
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GroupBy {
+    Role,
+    Scope,
+    None,
+}
+
+/// Order of the roles within a group of the menu
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortBy {
+    Name,
+    Expiry,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Source {
+    Group,
+    AzureRole,
+    EntraRole,
+}
+
+impl Display for Source {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Source::Group => write!(f, "Group"),
+            Source::AzureRole => write!(f, "Azure role"),
+            Source::EntraRole => write!(f, "Entra role"),
+        }
+    }
+}
+
+/// Time window of an active assignment
+#[derive(Clone)]
+pub struct ActiveAssignment {
+    pub start: Option<DateTime<Utc>>,
+    pub end: Option<DateTime<Utc>>,
+}
+
+impl ActiveAssignment {
+    fn new(start: Option<String>, end: Option<String>) -> ActiveAssignment {
+        ActiveAssignment {
+            start: start.and_then(|start| parse_time(&start)),
+            end: end.and_then(|end| parse_time(&end)),
+        }
+    }
+
+    /// Human readable time left of the assignment, like `expires in 1h 5m`
+    fn expires_in(&self) -> String {
+        let Some(end) = self.end else {
+            return "permanent".to_owned();
+        };
+        // Minutes are truncated, so the last minute is compared in seconds
+        let left = end - Utc::now();
+        let minutes = left.num_minutes();
+        match minutes {
+            _ if left.num_seconds() <= 0 => "expired".to_owned(),
+            0 => "expires in <1m".to_owned(),
+            1..=59 => format!("expires in {}m", minutes),
+            60..=1439 => format!("expires in {}h {}m", minutes / 60, minutes % 60),
+            _ => format!("expires in {}d {}h", minutes / 1440, minutes % 1440 / 60),
+        }
+    }
+}
+
+/// Human readable time left of an eligibility, like `ends in 3d`
+fn eligibility_ends_in(end: &DateTime<Utc>) -> String {
+    let minutes = (*end - Utc::now()).num_minutes();
+    match minutes {
+        ..=0 => "ended".to_owned(),
+        1..=1439 => "ends within a day".to_owned(),
+        _ => format!("ends in {}d", minutes / 1440),
+    }
+}
+
+/// Active assignments keyed by the resource and role definition they were activated for
+type ActiveAssignments = HashMap<(String, String), ActiveAssignment>;
+
+struct Clients {
+    graph_client: graph::GraphClient,
+    management_client: management::ManagementClient,
+}
+
+impl Clients {
+    fn new(config: &Config) -> Clients {
+        Clients {
+            graph_client: graph::GraphClient::new(&config.endpoints.graph),
+            management_client: management::ManagementClient::new(&config.endpoints.management),
+        }
+    }
+}
+
+/// Eligible roles of every source
+struct Pims<'a> {
+    group_pim: Vec<GroupPim<'a>>,
+    role_pim: Vec<RolePim<'a>>,
+    aad_pim: Vec<AadRolePim<'a>>,
+    // Sources whose eligible roles could not be loaded
+    errors: Vec<(Source, String)>,
+    // Sources whose active roles could not be loaded, only loaded with `with_active`
+    active_errors: Vec<(Source, String)>,
+}
 
-    let group_pim = group_pim_info
-        .into_iter()
-        .map(|info| GroupPim::new(&pim_client, info))
-        .collect::<Vec<GroupPim>>();
+impl<'a> Pims<'a> {
+    /// Loads eligible roles from the cache, and with `with_active` which of them are currently active
+    /// Sources that are not enabled in the configuration are not loaded
+    async fn load(clients: &'a Clients, cache: &Cache, config: &Config, with_active: bool) -> Pims<'a> {
+        let enabled = |source: Source| config.sources.contains(&source);
+        let eligible = async {
+            join!(
+                async {
+                    if !enabled(Source::Group) {
+                        return Ok(vec![]);
+                    }
+                    cache.fetch_group_cache(async || { group::fetch_group_info(&clients.graph_client).await }).await
+                },
+                async {
+                    if !enabled(Source::AzureRole) {
+                        return Ok(vec![]);
+                    }
+                    cache.fetch_role_info_cache(async || { role::fetch_role_info(&clients.management_client).await }).await
+                },
+                async {
+                    if !enabled(Source::EntraRole) {
+                        return Ok(vec![]);
+                    }
+                    cache.fetch_aad_role_cache(async || { aad_roles::fetch_aad_role_info(&clients.graph_client).await }).await
+                }
+            ).await
+        };
+        let active = async {
+            if with_active {
+                join!(
+                    async {
+                        if enabled(Source::Group) { group::fetch_active_assignments(&clients.graph_client).await } else { Ok(Default::default()) }
+                    },
+                    async {
+                        if enabled(Source::AzureRole) { role::fetch_active_assignments(&clients.management_client).await } else { Ok(Default::default()) }
+                    },
+                    async {
+                        if enabled(Source::EntraRole) { aad_roles::fetch_active_assignments(&clients.graph_client).await } else { Ok(Default::default()) }
+                    }
+                ).await
+            } else {
+                (Ok(Default::default()), Ok(Default::default()), Ok(Default::default()))
+            }
+        };
+        let (
+            (group_pim_info, role_pim_info, aad_pim_info),
+            (active_groups, active_roles, active_aad_roles)
+        ) = join!(eligible, active).await;
 
-    let role_pim = role_pim_info
-        .into_iter()
-        .map(|info| RolePim::new(&management_client, info))
-        .collect::<Vec<RolePim>>();
+        let mut errors = Vec::new();
+        let group_pim_info = loaded(Source::Group, group_pim_info, &mut errors);
+        let role_pim_info = loaded(Source::AzureRole, role_pim_info, &mut errors);
+        let aad_pim_info = loaded(Source::EntraRole, aad_pim_info, &mut errors);
+        let mut active_errors = Vec::new();
+        let mut active_groups = loaded(Source::Group, active_groups, &mut active_errors);
+        let mut active_roles = loaded(Source::AzureRole, active_roles, &mut active_errors);
+        let mut active_aad_roles = loaded(Source::EntraRole, active_aad_roles, &mut active_errors);
 
-    let aad_pim = aad_pim_info
-        .into_iter()
-        .map(|info| AadRolePim::new(&pim_client, info))
-        .collect::<Vec<AadRolePim>>();
+        Pims {
+            group_pim: group_pim_info
+                .into_iter()
+                .map(|info| {
+                    let active = active_groups.remove(&info.assignment_key());
+                    GroupPim::new(&clients.graph_client, info, active)
+                })
+                .collect(),
+            role_pim: role_pim_info
+                .into_iter()
+                .map(|info| {
+                    let active = active_roles.remove(&info.assignment_key());
+                    RolePim::new(&clients.management_client, info, active)
+                })
+                .collect(),
+            aad_pim: aad_pim_info
+                .into_iter()
+                .map(|info| {
+                    let active = active_aad_roles.remove(&info.assignment_key());
+                    AadRolePim::new(&clients.graph_client, info, active)
+                })
+                .collect(),
+            errors,
+            active_errors,
+        }
+    }
 
-    let grouped_group_pim = group(&group_pim);
-    let grouped_role_pim = group(&role_pim);
-    let grouped_aad_pim = group(&aad_pim);
+    /// Prints the sources that could not be loaded, for commands that do not show the menu
+    fn report_errors(&self) {
+        self.errors.iter().for_each(|(source, error)| eprintln!("[{}] Failed to load eligible roles: {}", source, error));
+        self.report_active_errors();
+    }
 
-    let mut pim_indexed: Vec<&dyn Pim> = Vec::with_capacity(group_pim.len() + role_pim.len() + aad_pim.len());
+    /// Prints the sources whose active roles could not be loaded, the menu shows the other errors
+    fn report_active_errors(&self) {
+        self.active_errors.iter().for_each(|(source, error)| eprintln!("[{}] Failed to load active roles: {}", source, error));
+    }
 
-    print_and_add(&grouped_group_pim, pim_indexed.as_mut());
-    print_and_add(&grouped_role_pim, pim_indexed.as_mut());
-    print_and_add(&grouped_aad_pim, pim_indexed.as_mut());
+    /// Tells there are no active roles, unless some could not be loaded, in which case pimple exits with an error
+    fn no_active_roles(&self, message: &str) {
+        if !self.active_errors.is_empty() {
+            std::process::exit(1);
+        }
+        println!("{}", message);
+    }
 
+    /// Eligible roles whose eligibility ends within the number of days
+    fn expiring(&self, days: i64) -> Vec<&dyn Pim<'_>> {
+        let limit = Utc::now() + chrono::Duration::days(days);
+        let mut expiring = self.all()
+            .into_iter()
+            .filter(|pim| pim.eligibility_end().is_some_and(|end| end <= limit))
+            .collect::<Vec<_>>();
+        expiring.sort_by_key(|pim| pim.eligibility_end());
+        expiring
+    }
+
+    /// Warns about eligibilities that end within the number of days
+    fn warn_expiring(&self, days: i64) {
+        let expiring = self.expiring(days);
+        if expiring.is_empty() {
+            return;
+        }
+        expiring.iter().for_each(|pim| {
+            let end = pim.eligibility_end().unwrap();
+            eprintln!("Warning: [{}] {} eligibility {} ({})", pim.source(), pim.name(), eligibility_ends_in(&end), format_local(&end));
+        });
+        eprintln!("Run `pimple renew` to request an extension");
+    }
+
+    fn sort(&mut self, sort_by: SortBy, group_by: GroupBy) {
+        sort(&mut self.group_pim, sort_by, group_by);
+        sort(&mut self.role_pim, sort_by, group_by);
+        sort(&mut self.aad_pim, sort_by, group_by);
+    }
+
+    fn all(&self) -> Vec<&dyn Pim<'_>> {
+        self.group_pim.iter().map(|pim| pim as &dyn Pim)
+            .chain(self.role_pim.iter().map(|pim| pim as &dyn Pim))
+            .chain(self.aad_pim.iter().map(|pim| pim as &dyn Pim))
+            .collect()
+    }
+}
+
+fn loaded<T: Default>(source: Source, result: Result<T, String>, errors: &mut Vec<(Source, String)>) -> T {
+    result.unwrap_or_else(|error| {
+        errors.push((source, error));
+        T::default()
+    })
+}
+
+pub async fn pim(args: &PimArgs, config: &Config) {
+    if (args.profile.is_some() || args.project) && args.command.is_some() {
+        eprintln!("--profile and --project can not be used with activate, select roles with --role instead");
+        std::process::exit(1);
+    }
+    let cache = Cache {
+        refresh: args.refresh,
+        ttl: config.cache_ttl(),
+    };
+    let clients = Clients::new(config);
+    let interactive = args.command.is_none() && args.profile.is_none() && !args.project;
+    let mut pims = Pims::load(&clients, &cache, config, interactive).await;
+    if let Some(access) = args.access {
+        pims.group_pim.retain(|pim| pim.access() == access);
+    }
+    pims.sort(config.sort_by, config.group_by);
+    pims.warn_expiring(config.expiry_warning_days);
+
+    if let Some(start) = &args.start {
+        println!("Activation will start {}", format_local(start));
+    }
+    let (activations, complete) = if let Some(PimCommands::Activate(activate_args)) = &args.command {
+        pims.report_errors();
+        (activate::activate(activate_args, args, config, &pims.all()).await, true)
+    } else if let Some(name) = &args.profile {
+        let Some(profile) = config.profiles.get(name) else {
+            eprintln!("There is no profile named '{}', the configured profiles are: {}", name, config.profiles.keys().cloned().collect::<Vec<_>>().join(", "));
+            std::process::exit(1);
+        };
+        pims.report_errors();
+        activate::activate_profile(&format!("the profile '{}'", name), profile, args, config, &pims.all()).await
+    } else if args.project {
+        let Some(project) = &config.project else {
+            eprintln!("There is no .pimple.toml in the working directory or any of its parents");
+            std::process::exit(1);
+        };
+        pims.report_errors();
+        activate::activate_profile("this project", &project.profile, args, config, &pims.all()).await
+    } else {
+        pims.report_active_errors();
+        let all = pims.all();
+        let project = config.project.as_ref()
+            .map(|project| activate::resolve_profile("this project", &project.profile, &all).0)
+            .unwrap_or_default();
+        (select_and_activate(&pims.group_pim, &pims.role_pim, &pims.aad_pim, &project, &pims.errors, args, config).await, true)
+    };
+
+    let failed = !complete || activations.iter().any(|(_, request_id)| request_id.is_none());
+    let requests = activations.into_iter()
+        .filter_map(|(pim, request_id)| request_id.map(|request_id| (pim, request_id)))
+        .collect::<Vec<_>>();
+    let provisioned = !args.wait || wait::wait(requests, Duration::from_secs(args.wait_timeout)).await;
+    if failed || !provisioned {
+        std::process::exit(1);
+    }
+}
+
+async fn select_and_activate<'a>(
+    group_pim: &'a Vec<GroupPim<'a>>,
+    role_pim: &'a Vec<RolePim<'a>>,
+    aad_pim: &'a Vec<AadRolePim<'a>>,
+    project: &[&'a dyn Pim<'a>],
+    errors: &[(Source, String)],
+    args: &PimArgs,
+    config: &Config,
+) -> Vec<(&'a dyn Pim<'a>, Option<String>)> {
+    let mut menu = Vec::with_capacity(project.len() + group_pim.len() + role_pim.len() + aad_pim.len());
+    add_project(project, &mut menu);
+    add_grouped(&group(group_pim, config.group_by), &mut menu);
+    add_empty_source(Source::Group, group_pim.len(), errors, config, &mut menu);
+    match config.group_by {
+        GroupBy::Scope => add_scope_tree(role_pim, &mut menu),
+        _ => add_grouped(&group(role_pim, config.group_by), &mut menu),
+    }
+    add_empty_source(Source::AzureRole, role_pim.len(), errors, config, &mut menu);
+    add_grouped(&group(aad_pim, config.group_by), &mut menu);
+    add_empty_source(Source::EntraRole, aad_pim.len(), errors, config, &mut menu);
+
+    // Only read once the picker is done, so the picker gets the keys
     let reader = BufReader::new(tokio::io::stdin());
     let mut lines = reader.lines();
-    let roles_string = prompt(&mut lines, "Please select role(s) separated by ',': ").await;
-    let roles = roles_string.split(",")
-        .map(|role| role.parse::<usize>().expect("Role ID(s) must be numeric"));
+    let use_picker = !args.no_picker && std::io::stdin().is_terminal() && std::io::stdout().is_terminal();
+    let selected = if use_picker {
+        match pick_from_menu(&menu) {
+            Some(selected) if !selected.is_empty() => selected,
+            _ => {
+                println!("No roles selected");
+                return vec![];
+            }
+        }
+    } else {
+        print_menu(&menu);
+        loop {
+            let selection = prompt(&mut lines, "Please select role(s) by number, range, name or group separated by ',', prefix with ! to exclude: ").await;
+            match selection::select(&menu, &selection) {
+                Ok(selected) if !selected.is_empty() => break selected,
+                Ok(_) => println!("The selection does not include any role"),
+                Err(error) => println!("{}", error),
+            }
+        }
+    };
+    let prompts = async {
+        let policies = policy::policies(&selected).await;
+        let (durations, reason) = reason_and_durations(&mut lines, &selected, &policies, config).await;
+        (policies, durations, reason)
+    };
+    let ((policies, durations, reason), _) = join!(
+        prompts,
+        join_all(selected.iter().map(|pim| pim.ensure_token()))
+    ).await;
+    let ticket = ticket_or_prompt(&mut lines, args.ticket(), &policies).await;
+
+    let requests = selected.into_iter()
+        .zip(durations)
+        .map(|(pim, duration)| (pim, Activation {
+            reason: reason.clone(),
+            duration,
+            start: args.start,
+            ticket: ticket.clone(),
+        }))
+        .collect::<Vec<_>>();
+    if let Err(violations) = policy::check(&requests, &policies) {
+        eprintln!("{}", violations);
+        std::process::exit(1);
+    }
+    if use_picker && !confirm(&mut lines, &requests, &reason, &ticket).await {
+        println!("Nothing was requested");
+        return vec![];
+    }
+    activate_all(requests).await
+}
+
+/// Shows what is about to be requested and asks for confirmation
+async fn confirm<T>(
+    lines: &mut Lines<T>,
+    requests: &[(&dyn Pim<'_>, Activation)],
+    reason: &str,
+    ticket: &Option<Ticket>,
+) -> bool where T: AsyncBufRead + Unpin {
+    println!();
+    requests.iter().for_each(|(pim, activation)| {
+        let start = activation.start.unwrap_or_else(Utc::now);
+        match schedule::parse_iso_duration(&activation.duration) {
+            Some(duration) => println!("[{}] {} for {}, until {}", pim.source(), pim.name(), activation.duration, format_local(&(start + duration))),
+            None => println!("[{}] {} for {}", pim.source(), pim.name(), activation.duration),
+        }
+    });
+    println!("Reason: {}", reason);
+    if let Some(ticket) = ticket {
+        println!("Ticket: {} {}", ticket.system, ticket.number);
+    }
+    let answer = prompt(lines, "Submit? [Y/n]: ").await;
+    answer.is_empty() || answer.eq_ignore_ascii_case("y") || answer.eq_ignore_ascii_case("yes")
+}
+
+/// Submits activation of all roles concurrently, pairing each role with its request id if the request was accepted
+async fn activate_all<'a>(requests: Vec<(&'a dyn Pim<'a>, Activation)>) -> Vec<(&'a dyn Pim<'a>, Option<String>)> {
+    join_all(requests.into_iter().map(|(pim, activation)| {
+        let request = pim.activate(activation);
+        async move { (pim, request.await) }
+    })).await
+}
 
-    let (duration, reason) = {
-        let handles = (&roles).clone().into_iter()
-            .map(|v| pim_indexed[v - 1].ensure_token());
+/// Prints the outcome of an activation request, returning the request id if it was accepted
+fn submitted(name: String, result: Result<(String, String), String>) -> Option<String> {
+    match result {
+        Ok((request_id, status)) => {
+            println!("{}: {}", name, status);
+            Some(request_id)
+        }
+        Err(error) => {
+            println!("{}: {}", name, error);
+            None
+        }
+    }
+}
+
+async fn reason_and_durations<T>(
+    lines: &mut Lines<T>,
+    pims: &[&dyn Pim<'_>],
+    policies: &[Policy],
+    config: &Config,
+) -> (Vec<String>, String) where T: AsyncBufRead + Unpin {
+    let durations = durations(lines, pims, policies, config, "Select a duration, example `30m`, `1h30m`, `PT1H` or `max`").await;
+    let justification_required = policies.iter().any(|policy| policy.justification_required);
+    let default_reason = config.reason();
+    let prompt_text = match &default_reason {
+        Some(reason) => format!("Reason (leave empty for `{}`): ", reason),
+        None => "Reason: ".to_owned(),
+    };
+    loop {
+        let reason = match (prompt(lines, &prompt_text).await, &default_reason) {
+            (reason, Some(default_reason)) if reason.is_empty() => default_reason.to_owned(),
+            (reason, _) => reason,
+        };
+        if reason.is_empty() && justification_required {
+            println!("A justification is required to activate the selected roles");
+            continue;
+        }
+        return (durations, reason);
+    }
+}
 
-        let (prompts, _) = join!(reason_and_duration(&mut lines), join_all(handles)).await;
-        prompts
+/// Prompts until a duration is given that can be requested for every role, defaulting to the configured duration or
+/// the maximum of each role. Durations longer than a role allows are clamped to its maximum.
+async fn durations<T>(
+    lines: &mut Lines<T>,
+    pims: &[&dyn Pim<'_>],
+    policies: &[Policy],
+    config: &Config,
+    prompt_text: &str,
+) -> Vec<String> where T: AsyncBufRead + Unpin {
+    let default = match &config.duration {
+        Some(duration) => format!("`{}`", duration),
+        None if pims.len() > 1 => "the maximum of each role".to_owned(),
+        None => "the maximum".to_owned(),
+    };
+    let prompt_text = if pims.len() > 1 {
+        format!("{}, override it for a role like `name=30m`, leave empty for {}: ", prompt_text, default)
+    } else {
+        format!("{}, leave empty for {}: ", prompt_text, default)
     };
+    loop {
+        let durations = prompt(lines, &prompt_text).await;
+        match parse_durations(&durations, pims, config.duration()).and_then(|(default, overrides)| policy::durations(pims, policies, default, &overrides)) {
+            Ok(durations) => return durations,
+            Err(error) => println!("{}", error),
+        }
+    }
+}
 
-    join_all(roles.map(|role| {
-        pim_indexed[role - 1].activate(reason.clone(), duration.clone())
-    })).await;
+/// Parses a duration for every role followed by overrides for the roles whose name contains a text,
+/// like `1h, aks=30m`, using the default when no duration is given for every role
+fn parse_durations(
+    durations: &str,
+    pims: &[&dyn Pim],
+    mut default: RequestedDuration,
+) -> Result<(RequestedDuration, Vec<Option<RequestedDuration>>), String> {
+    let mut overrides = vec![None; pims.len()];
+    for (index, duration) in durations.split(',').map(str::trim).enumerate() {
+        match duration.split_once('=') {
+            Some((name, duration)) => {
+                let name = name.trim().to_lowercase();
+                let matches = pims.iter()
+                    .enumerate()
+                    .filter(|(_, pim)| pim.name().to_lowercase().contains(&name))
+                    .map(|(index, _)| index)
+                    .collect::<Vec<usize>>();
+                let [role] = matches.as_slice() else {
+                    return Err(format!("'{}' must match exactly one of the selected roles", name));
+                };
+                overrides[*role] = Some(RequestedDuration::parse(duration)?);
+            }
+            None if index == 0 => {
+                if !duration.is_empty() {
+                    default = RequestedDuration::parse(duration)?;
+                }
+            }
+            None => return Err("Only the first duration applies to every role, override it for a role like `name=30m`".to_owned()),
+        }
+    }
+    Ok((default, overrides))
 }
 
-async fn reason_and_duration<T>(lines: &mut Lines<T>) -> (String, String) where T: AsyncBufRead + Unpin {
-    (
-        prompt(lines, "Select a duration(ISO8601, example `PT30M` or `PT1H`): ").await,
-        prompt(lines, "Reason: ").await
-    )
+/// Prompts for a ticket if any of the policies requires one and none was given on the command line
+async fn ticket_or_prompt<T>(lines: &mut Lines<T>, ticket: Option<Ticket>, policies: &[Policy]) -> Option<Ticket> where T: AsyncBufRead + Unpin {
+    if ticket.is_some() || !policies.iter().any(|policy| policy.ticket_required) {
+        return ticket;
+    }
+    let number = loop {
+        let number = prompt(lines, "Ticket number: ").await;
+        if !number.is_empty() {
+            break number;
+        }
+        println!("A ticket number is required to activate the selected roles");
+    };
+    Some(Ticket {
+        number,
+        system: prompt(lines, "Ticket system: ").await,
+    })
 }
 
-fn group<'a, T>(pims: &'a Vec<T>) -> Vec<&[T]> where T: Pim<'a> {
-    pims.as_slice()
-        .group_by(|a, b| a.group_by() == b.group_by())
-        .collect()
+/// Splits sorted roles into the groups shown in the menu, with the header of each group
+fn group<'a, T>(pims: &'a [T], group_by: GroupBy) -> Vec<(Option<String>, &'a [T])> where T: Pim<'a> {
+    if pims.is_empty() {
+        return vec![];
+    }
+    match group_by {
+        GroupBy::None => vec![(None, pims)],
+        _ => pims.chunk_by(|a, b| a.group_by() == b.group_by())
+            .map(|values| (Some(values[0].group_by()), values))
+            .collect(),
+    }
+}
+
+/// Sorts roles by their group unless they are not grouped, then by the sort key, with their name and ids as tiebreakers
+/// so the menu is numbered the same on every run with the same cache
+fn sort<'a, T>(pims: &mut [T], sort_by: SortBy, group_by: GroupBy) where T: Pim<'a> {
+    pims.sort_by_cached_key(|pim| {
+        let group = match group_by {
+            GroupBy::None => None,
+            GroupBy::Role | GroupBy::Scope => Some(pim.group_by()),
+        };
+        let end = match sort_by {
+            SortBy::Name => None,
+            // Permanent eligibilities last
+            SortBy::Expiry => Some((pim.eligibility_end().is_none(), pim.eligibility_end())),
+        };
+        (group, end, pim.resource_id(), pim.name(), pim.selectors())
+    });
 }
 
-async fn prompt<T>(lines: &mut Lines<T>, prompt: &'static str) -> String where T: AsyncBufRead + Unpin {
+async fn prompt<T>(lines: &mut Lines<T>, prompt: &str) -> String where T: AsyncBufRead + Unpin {
     print!("{}", prompt);
     std::io::stdout().flush().unwrap();
     lines.next_line().await.unwrap().unwrap().trim().to_owned()
 }
 
-fn print_and_add<'a, T>(grouped: &Vec<&'a [T]>, pim_indexed: &mut Vec<&'a dyn Pim<'a>>) where T: Pim<'a> {
-    grouped.into_iter().for_each(|values| {
-        println!("{}", values.first().unwrap().group_by());
-        values.into_iter().for_each(|group| {
-            pim_indexed.push(group);
-            println!("{}.\t{}", pim_indexed.len(), group.resource_id());
+fn parse_time(timestamp: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(timestamp)
+        .ok()
+        .map(|time| time.with_timezone(&Utc))
+}
+
+/// Formats a timestamp returned by Azure in the local timezone
+fn local_time(timestamp: &str) -> String {
+    parse_time(timestamp)
+        .map(|time| format_local(&time))
+        .unwrap_or_else(|| timestamp.to_owned())
+}
+
+/// Duration and end of a request to Microsoft Graph, from its schedule
+fn graph_expiration(schedule_info: &Option<graph::ScheduleInfo>) -> (Option<String>, Option<String>) {
+    schedule_info.as_ref()
+        .and_then(|schedule| schedule.expiration.as_ref())
+        .map(|expiration| (expiration.duration.clone(), expiration.end_date_time.clone()))
+        .unwrap_or_default()
+}
+
+/// How long a request is for, its duration if it has one, otherwise when it ends
+fn requested_duration(duration: Option<String>, end: Option<String>) -> String {
+    match (duration, end) {
+        (Some(duration), _) => duration,
+        (None, Some(end)) => format!("until {}", local_time(&end)),
+        (None, None) => "permanent".to_owned(),
+    }
+}
+
+fn format_local(time: &DateTime<Utc>) -> String {
+    time.with_timezone(&Local).format("%Y-%m-%d %H:%M").to_string()
+}
+
+/// Lists the active roles and prompts until a valid selection of them is made
+async fn select_active<'a, T>(
+    lines: &mut Lines<T>,
+    active: &[&'a dyn Pim<'a>],
+    prompt_text: &'static str,
+) -> Vec<&'a dyn Pim<'a>> where T: AsyncBufRead + Unpin {
+    active.iter().enumerate().for_each(|(index, pim)| {
+        match pim.active().and_then(|active| active.end.map(|end| (end, active.expires_in()))) {
+            Some((end, expires_in)) => println!("{}.\t[{}] {} (until {}, {})", index + 1, pim.source(), pim.name(), format_local(&end), expires_in),
+            None => println!("{}.\t[{}] {}", index + 1, pim.source(), pim.name()),
+        }
+    });
+    loop {
+        let selection = prompt(lines, prompt_text).await;
+        match selection::parse_selection(&selection, active.len()) {
+            Ok(indexes) => return indexes.into_iter().map(|index| active[index]).collect(),
+            Err(error) => println!("{}", error),
+        }
+    }
+}
+
+const PROJECT_HEADER: &str = "Activate roles required by this project";
+
+/// A line of the role menu, headers are indented by their depth and entries are one level below their header
+enum MenuLine<'a> {
+    Header(usize, String),
+    Entry(usize, String, &'a dyn Pim<'a>),
+}
+
+fn add_grouped<'a, T>(grouped: &[(Option<String>, &'a [T])], menu: &mut Vec<MenuLine<'a>>) where T: Pim<'a> {
+    grouped.iter().for_each(|(header, values)| {
+        match header {
+            Some(header) => {
+                menu.push(MenuLine::Header(0, header.to_owned()));
+                values.iter().for_each(|pim| menu.push(MenuLine::Entry(1, pim.resource_id(), pim)));
+            }
+            // Without a header the resource alone does not tell which role it is
+            None => values.iter().for_each(|pim| menu.push(MenuLine::Entry(1, format!("[{}] {}", pim.source(), pim.name()), pim))),
+        }
+    });
+}
+
+/// Adds the roles required by the project first, under a header selecting all of them
+fn add_project<'a>(project: &[&'a dyn Pim<'a>], menu: &mut Vec<MenuLine<'a>>) {
+    if project.is_empty() {
+        return;
+    }
+    menu.push(MenuLine::Header(0, PROJECT_HEADER.to_owned()));
+    project.iter().for_each(|pim| menu.push(MenuLine::Entry(1, format!("[{}] {}", pim.source(), pim.name()), *pim)));
+}
+
+/// Adds Azure roles under the management group, subscription, resource group and resource they are eligible at
+fn add_scope_tree<'a>(role_pim: &'a [RolePim<'a>], menu: &mut Vec<MenuLine<'a>>) {
+    let mut tree = role_pim.iter()
+        .map(|pim| (pim.scope_path(), pim))
+        .collect::<Vec<_>>();
+    // Stable, so roles at the same scope keep their order
+    tree.sort_by(|(a, _), (b, _)| a.cmp(b));
+    let mut previous: Vec<String> = Vec::new();
+    tree.into_iter().for_each(|(path, pim)| {
+        let shared = previous.iter().zip(&path).take_while(|(a, b)| a == b).count();
+        path.iter().enumerate().skip(shared).for_each(|(depth, level)| menu.push(MenuLine::Header(depth, level.to_owned())));
+        menu.push(MenuLine::Entry(path.len(), pim.role_label(), pim));
+        previous = path;
+    });
+}
+
+/// Tells why a source has no roles in the menu, either it failed to load or there are no eligible roles
+fn add_empty_source(source: Source, count: usize, errors: &[(Source, String)], config: &Config, menu: &mut Vec<MenuLine>) {
+    if count > 0 || !config.sources.contains(&source) {
+        return;
+    }
+    match errors.iter().find(|(failed, _)| *failed == source) {
+        Some((_, error)) => menu.push(MenuLine::Header(0, format!("[{}] Failed to load eligible roles: {}", source, error))),
+        None => menu.push(MenuLine::Header(0, format!("[{}] No eligible roles", source))),
+    }
+}
+
+/// Prints the menu with numbered entries
+fn print_menu(menu: &[MenuLine]) {
+    let mut number = 0;
+    menu.iter().for_each(|line| match line {
+        MenuLine::Header(depth, text) => println!("{}{}", "  ".repeat(*depth), text),
+        MenuLine::Entry(depth, label, pim) => {
+            number += 1;
+            println!("{}.\t{}{}{}", number, "  ".repeat(depth - 1), label, details(*pim));
+        }
+    });
+}
+
+/// Lets the user select roles from the menu in the full-screen picker, None if the picker was cancelled
+fn pick_from_menu<'a>(menu: &[MenuLine<'a>]) -> Option<Vec<&'a dyn Pim<'a>>> {
+    let lines = menu.iter()
+        .map(|line| match line {
+            MenuLine::Header(depth, text) => picker::PickerLine { depth: *depth, text: text.to_owned(), selectable: false },
+            MenuLine::Entry(depth, label, pim) => picker::PickerLine { depth: *depth, text: format!("{}{}", label, details(*pim)), selectable: true },
         })
+        .collect::<Vec<_>>();
+    let selected = picker::pick(&lines)?;
+    Some(unique(selected.into_iter()
+        .filter_map(|index| match &menu[index] {
+            MenuLine::Entry(_, _, pim) => Some(*pim),
+            MenuLine::Header(..) => None,
+        })))
+}
+
+/// Removes roles selected more than once, like roles required by the project also selected further down the menu
+fn unique<'a>(pims: impl Iterator<Item=&'a dyn Pim<'a>>) -> Vec<&'a dyn Pim<'a>> {
+    let mut unique: Vec<&'a dyn Pim<'a>> = Vec::new();
+    pims.for_each(|pim| {
+        if !unique.iter().any(|selected| std::ptr::addr_eq(*selected, pim)) {
+            unique.push(pim);
+        }
     });
+    unique
+}
+
+/// Requirements, eligibility end and activation of the role, as shown after its name in the menu
+fn details(pim: &dyn Pim) -> String {
+    let requirements = match pim.cached_policy().map(|policy| policy.requirements()) {
+        Some(requirements) if requirements.is_empty() => String::new(),
+        Some(requirements) => format!(" [{}]", requirements.join(", ")),
+        None => " [requirements unknown]".to_owned(),
+    };
+    let eligibility = pim.eligibility_end()
+        .map(|end| format!(" (eligible until {})", format_local(&end)))
+        .unwrap_or_default();
+    match pim.active() {
+        Some(active) => format!("{}{} (active, {})", requirements, eligibility, active.expires_in()),
+        None => format!("{}{}", requirements, eligibility),
+    }
 }
 
 trait Pim<'a> {
+    fn source(&self) -> Source;
     fn group_by(&self) -> String;
     fn resource_id(&self) -> String;
+    /// Unambiguous human readable name, used when reporting on a role
+    fn name(&self) -> String;
+    /// Names and ids that can be used to select this role from the command line
+    fn selectors(&self) -> Vec<String>;
     fn ensure_token(&self) -> Pin<Box<dyn Future<Output=()> + 'a>>;
-    fn activate(&self, reason: String, duration: String) -> Pin<Box<dyn Future<Output = ()> + 'a>>;
+    /// Requests activation, resolving to the request id if the request was accepted
+    fn activate(&self, activation: Activation) -> Pin<Box<dyn Future<Output = Option<String>> + 'a>>;
+    /// Current status of an activation request, as reported by Azure, or why it could not be read
+    fn request_status(&self, request_id: String) -> Pin<Box<dyn Future<Output = Result<String, String>> + 'a>>;
+    /// The active assignment of this role, only known when loaded with active assignments
+    fn active(&self) -> Option<&ActiveAssignment>;
+    /// Requests deactivation, resolving to the request id if the request was accepted
+    fn deactivate(&self) -> Pin<Box<dyn Future<Output = Option<String>> + 'a>>;
+    /// Requirements for activating this role, from the cache when available
+    fn policy(&self) -> Pin<Box<dyn Future<Output = Result<Policy, String>> + 'a>>;
+    /// Requirements for activating this role if they are in the cache
+    fn cached_policy(&self) -> Option<&Policy>;
+    /// Reason given for the latest activation of this role, None if there is none or the requests could not be read
+    fn last_reason(&self) -> Pin<Box<dyn Future<Output = Option<String>> + 'a>>;
+    /// When the eligibility for this role ends, None if it is permanent
+    fn eligibility_end(&self) -> Option<DateTime<Utc>>;
+    /// Requests extension of the eligibility, or renewal once it has ended, resolving to the request id if the request was accepted
+    fn renew(&self, activation: Activation) -> Pin<Box<dyn Future<Output = Option<String>> + 'a>>;
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, Utc};
+
+    use crate::cmd::pim::policy::RequestedDuration;
+    use crate::cmd::pim::test_pim::TestPim;
+    use crate::cmd::pim::{group, parse_durations, sort, GroupBy, Pim, SortBy};
+
+    #[test]
+    fn parses_a_duration_with_overrides_for_roles() {
+        let pims = [TestPim::new("prod-aks"), TestPim::new("Global Reader")];
+        let pims = pims.iter().map(|pim| pim as &dyn Pim).collect::<Vec<_>>();
+
+        let (default, overrides) = parse_durations("1h, reader=max", &pims, RequestedDuration::Maximum).unwrap();
+        assert!(matches!(default, RequestedDuration::Length(length) if length == Duration::hours(1)));
+        assert!(overrides[0].is_none());
+        assert!(matches!(overrides[1], Some(RequestedDuration::Maximum)));
+
+        let (default, overrides) = parse_durations("aks=30m", &pims, RequestedDuration::Length(Duration::hours(2))).unwrap();
+        assert!(matches!(default, RequestedDuration::Length(length) if length == Duration::hours(2)));
+        assert!(matches!(overrides[0], Some(RequestedDuration::Length(length)) if length == Duration::minutes(30)));
+    }
+
+    #[test]
+    fn rejects_overrides_not_matching_exactly_one_role() {
+        let pims = [TestPim::new("prod-aks"), TestPim::new("prod-reader")];
+        let pims = pims.iter().map(|pim| pim as &dyn Pim).collect::<Vec<_>>();
+        assert!(parse_durations("1h, prod=30m", &pims, RequestedDuration::Maximum).is_err());
+        assert!(parse_durations("1h, staging=30m", &pims, RequestedDuration::Maximum).is_err());
+        assert!(parse_durations("1h, 2h", &pims, RequestedDuration::Maximum).is_err());
+    }
+
+    fn grouped(name: &str, group: &str) -> TestPim {
+        TestPim {
+            group: group.to_owned(),
+            ..TestPim::new(name)
+        }
+    }
+
+    fn ending(name: &str, days: Option<i64>) -> TestPim {
+        let now = Utc::now();
+        TestPim {
+            eligibility_end: days.map(|days| now + Duration::days(days)),
+            ..TestPim::new(name)
+        }
+    }
+
+    fn names(pims: &[TestPim]) -> Vec<String> {
+        pims.iter().map(|pim| pim.name()).collect()
+    }
+
+    fn headers(pims: &[TestPim], group_by: GroupBy) -> Vec<Option<String>> {
+        group(pims, group_by).into_iter().map(|(header, _)| header).collect()
+    }
+
+    #[test]
+    fn sorts_shuffled_roles_the_same_with_one_header_per_group() {
+        let roles = [("reader-test", "Readers"), ("owner-prod", "Owners"), ("reader-prod", "Readers"), ("admin", "Admins"), ("owner-test", "Owners")];
+        let mut shuffled = roles.iter().map(|(name, group)| grouped(name, group)).collect::<Vec<_>>();
+        let mut reversed = roles.iter().rev().map(|(name, group)| grouped(name, group)).collect::<Vec<_>>();
+        sort(&mut shuffled, SortBy::Name, GroupBy::Role);
+        sort(&mut reversed, SortBy::Name, GroupBy::Role);
+
+        assert_eq!(names(&shuffled), ["admin", "owner-prod", "owner-test", "reader-prod", "reader-test"]);
+        assert_eq!(names(&reversed), names(&shuffled));
+        let expected = ["Admins", "Owners", "Readers"].map(|header| Some(header.to_owned()));
+        assert_eq!(headers(&shuffled, GroupBy::Role), expected);
+    }
+
+    #[test]
+    fn sorts_ungrouped_roles_by_name_alone_under_no_header() {
+        let mut pims = [grouped("b", "Readers"), grouped("c", "Admins"), grouped("a", "Readers")];
+        sort(&mut pims, SortBy::Name, GroupBy::None);
+        assert_eq!(names(&pims), ["a", "b", "c"]);
+        assert_eq!(headers(&pims, GroupBy::None), [None]);
+    }
+
+    #[test]
+    fn sorts_by_expiry_with_permanent_eligibilities_last() {
+        let mut pims = [ending("permanent", None), ending("week", Some(7)), ending("day", Some(1)), ending("also-permanent", None)];
+        sort(&mut pims, SortBy::Expiry, GroupBy::None);
+        assert_eq!(names(&pims), ["day", "week", "also-permanent", "permanent"]);
+    }
 }
@@ -0,0 +1,281 @@
+use std::io::{stdout, Write};
+
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{read, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::style::{Attribute, Print, SetAttribute};
+use crossterm::terminal::{disable_raw_mode, enable_raw_mode, size, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+
+/// A line of the picker, either a header or an entry that can be selected
+pub struct PickerLine {
+    pub depth: usize,
+    pub text: String,
+    pub selectable: bool,
+}
+
+/// Restores the terminal when the picker is done, also when it panics
+struct Screen;
+
+impl Screen {
+    fn enter() -> Screen {
+        enable_raw_mode().unwrap();
+        execute!(stdout(), EnterAlternateScreen, Hide).unwrap();
+        Screen
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let _ = execute!(stdout(), Show, LeaveAlternateScreen);
+        let _ = disable_raw_mode();
+    }
+}
+
+struct Picker<'a> {
+    lines: &'a [PickerLine],
+    // Text each line is searched by, including the headers it is under
+    search_texts: Vec<String>,
+    selected: Vec<bool>,
+    query: String,
+    // Indexes of the lines matching the query
+    visible: Vec<usize>,
+    cursor: usize,
+    offset: usize,
+}
+
+impl<'a> Picker<'a> {
+    fn new(lines: &'a [PickerLine]) -> Picker<'a> {
+        let mut headers: Vec<&PickerLine> = Vec::new();
+        let search_texts = lines.iter()
+            .map(|line| {
+                headers.retain(|header| header.depth < line.depth);
+                let text = headers.iter()
+                    .map(|header| header.text.as_str())
+                    .chain([line.text.as_str()])
+                    .collect::<Vec<&str>>()
+                    .join(" ")
+                    .to_lowercase();
+                if !line.selectable {
+                    headers.push(line);
+                }
+                text
+            })
+            .collect();
+        let mut picker = Picker {
+            lines,
+            search_texts,
+            selected: vec![false; lines.len()],
+            query: String::new(),
+            visible: vec![],
+            cursor: 0,
+            offset: 0,
+        };
+        picker.filter();
+        picker
+    }
+
+    /// Entries under the header at the index, or the entry itself
+    fn children(&self, index: usize) -> Vec<usize> {
+        if self.lines[index].selectable {
+            return vec![index];
+        }
+        let depth = self.lines[index].depth;
+        self.lines.iter()
+            .enumerate()
+            .skip(index + 1)
+            .take_while(|(_, line)| line.depth > depth)
+            .filter(|(_, line)| line.selectable)
+            .map(|(child, _)| child)
+            .collect()
+    }
+
+    /// Entries matching the query, with the headers that have matching entries
+    fn filter(&mut self) {
+        let terms = self.query.to_lowercase();
+        let terms = terms.split_whitespace().collect::<Vec<&str>>();
+        let matches = self.search_texts.iter()
+            .zip(self.lines)
+            .map(|(text, line)| line.selectable && terms.iter().all(|term| fuzzy_match(text, term)))
+            .collect::<Vec<bool>>();
+        self.visible = (0..self.lines.len())
+            .filter(|index| matches[*index] || !self.lines[*index].selectable && self.children(*index).iter().any(|child| matches[*child]))
+            .collect();
+        self.cursor = self.cursor.min(self.visible.len().saturating_sub(1));
+    }
+
+    /// Selects the entry under the cursor, or all visible entries under a header, unless they are all selected already
+    fn toggle(&mut self) {
+        let Some(index) = self.visible.get(self.cursor).copied() else {
+            return;
+        };
+        let children = self.children(index)
+            .into_iter()
+            .filter(|child| self.visible.contains(child))
+            .collect::<Vec<usize>>();
+        let select = !children.iter().all(|child| self.selected[*child]);
+        children.into_iter().for_each(|child| self.selected[child] = select);
+    }
+
+    fn is_checked(&self, index: usize) -> bool {
+        let children = self.children(index);
+        !children.is_empty() && children.iter().all(|child| self.selected[*child])
+    }
+
+    fn selection(&self) -> Vec<usize> {
+        (0..self.lines.len()).filter(|index| self.selected[*index]).collect()
+    }
+
+    fn render(&mut self) {
+        let (width, height) = size().unwrap();
+        let rows = (height as usize).saturating_sub(3).max(1);
+        if self.cursor < self.offset {
+            self.offset = self.cursor;
+        } else if self.cursor >= self.offset + rows {
+            self.offset = self.cursor + 1 - rows;
+        }
+
+        let mut out = stdout();
+        queue!(out, Clear(ClearType::All), MoveTo(0, 0), Print(format!("Search: {}", self.query))).unwrap();
+        self.visible.iter()
+            .enumerate()
+            .skip(self.offset)
+            .take(rows)
+            .for_each(|(row, index)| {
+                let line = &self.lines[*index];
+                let checkbox = if self.is_checked(*index) { "[x]" } else { "[ ]" };
+                let text = format!("{}{} {}", "  ".repeat(line.depth), checkbox, line.text);
+                let text = text.chars().take(width as usize).collect::<String>();
+                queue!(out, MoveTo(0, (row - self.offset + 1) as u16)).unwrap();
+                if row == self.cursor {
+                    queue!(out, SetAttribute(Attribute::Reverse), Print(text), SetAttribute(Attribute::Reset)).unwrap();
+                } else {
+                    queue!(out, Print(text)).unwrap();
+                }
+            });
+        queue!(
+            out,
+            MoveTo(0, height.saturating_sub(1)),
+            Print(format!("{} selected, type to search, Tab to select, Enter to confirm, Esc to cancel", self.selection().len())),
+        ).unwrap();
+        out.flush().unwrap();
+    }
+}
+
+/// Whether the characters of the term appear in the text in order
+fn fuzzy_match(text: &str, term: &str) -> bool {
+    let mut chars = text.chars();
+    term.chars().all(|c| chars.any(|t| t == c))
+}
+
+/// Lets the user search and select entries in a full-screen picker, returning the indexes of the selected lines,
+/// or None if the picker was cancelled
+pub fn pick(lines: &[PickerLine]) -> Option<Vec<usize>> {
+    let _screen = Screen::enter();
+    let mut picker = Picker::new(lines);
+    loop {
+        picker.render();
+        let Event::Key(KeyEvent { code, modifiers, kind, .. }) = read().unwrap() else {
+            continue;
+        };
+        if kind == KeyEventKind::Release {
+            continue;
+        }
+        match code {
+            KeyCode::Esc => return None,
+            KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => return None,
+            KeyCode::Enter => {
+                // Confirming without a selection picks the entry under the cursor
+                if picker.selection().is_empty() {
+                    picker.toggle();
+                }
+                return Some(picker.selection());
+            }
+            // Space is part of the search, so terms can be separated
+            KeyCode::Tab => {
+                picker.toggle();
+                picker.cursor = (picker.cursor + 1).min(picker.visible.len().saturating_sub(1));
+            }
+            KeyCode::Up => picker.cursor = picker.cursor.saturating_sub(1),
+            KeyCode::Down => picker.cursor = (picker.cursor + 1).min(picker.visible.len().saturating_sub(1)),
+            KeyCode::PageUp => picker.cursor = picker.cursor.saturating_sub(10),
+            KeyCode::PageDown => picker.cursor = (picker.cursor + 10).min(picker.visible.len().saturating_sub(1)),
+            KeyCode::Backspace => {
+                picker.query.pop();
+                picker.filter();
+            }
+            KeyCode::Char(c) => {
+                picker.query.push(c);
+                picker.filter();
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{fuzzy_match, Picker, PickerLine};
+
+    fn lines() -> Vec<PickerLine> {
+        let line = |depth: usize, text: &str, selectable: bool| PickerLine { depth, text: text.to_owned(), selectable };
+        vec![
+            line(0, "Subscription Production", false),
+            line(1, "Resource group aks", false),
+            line(2, "Owner", true),
+            line(2, "Reader", true),
+            line(1, "Contributor", true),
+            line(0, "Subscription Staging", false),
+            line(1, "Reader", true),
+        ]
+    }
+
+    #[test]
+    fn matches_characters_in_order() {
+        assert!(fuzzy_match("global reader", "glrd"));
+        assert!(fuzzy_match("global reader", "reader"));
+        assert!(!fuzzy_match("global reader", "rg"));
+        assert!(!fuzzy_match("reader", "readers"));
+    }
+
+    #[test]
+    fn searches_entries_by_the_headers_they_are_under() {
+        let lines = lines();
+        let mut picker = Picker::new(&lines);
+        assert_eq!(picker.visible.len(), lines.len());
+
+        picker.query = "prod reader".to_owned();
+        picker.filter();
+        assert_eq!(picker.visible, [0, 1, 3]);
+
+        picker.query = "staging".to_owned();
+        picker.filter();
+        assert_eq!(picker.visible, [5, 6]);
+    }
+
+    #[test]
+    fn toggles_every_visible_entry_under_a_header() {
+        let lines = lines();
+        let mut picker = Picker::new(&lines);
+        picker.toggle();
+        assert_eq!(picker.selection(), [2, 3, 4]);
+        assert!(picker.is_checked(0) && picker.is_checked(1));
+        picker.toggle();
+        assert!(picker.selection().is_empty());
+
+        picker.query = "owner".to_owned();
+        picker.filter();
+        picker.toggle();
+        assert_eq!(picker.selection(), [2]);
+        assert!(!picker.is_checked(0));
+    }
+
+    #[test]
+    fn toggles_an_entry() {
+        let lines = lines();
+        let mut picker = Picker::new(&lines);
+        picker.cursor = 6;
+        picker.toggle();
+        assert_eq!(picker.selection(), [6]);
+        assert!(picker.is_checked(5));
+    }
+}
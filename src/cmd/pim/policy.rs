@@ -0,0 +1,299 @@
+use std::future::Future;
+
+use chrono::Duration;
+use futures::{stream, StreamExt};
+use serde::{Deserialize, Serialize};
+
+use crate::azure::{Activation, PolicyRule};
+use crate::cmd::pim::Pim;
+use crate::cmd::pim::schedule::{format_iso_duration, parse_duration, parse_iso_duration};
+
+/// Duration asked for when activating, either a length or the maximum each role allows
+#[derive(Clone, Copy)]
+pub enum RequestedDuration {
+    Maximum,
+    Length(Duration),
+}
+
+impl RequestedDuration {
+    /// Parses `max`, a duration like `30m` or `1h30m`, or an ISO8601 duration like `PT1H`
+    pub fn parse(value: &str) -> Result<RequestedDuration, String> {
+        if value.trim().eq_ignore_ascii_case("max") {
+            return Ok(RequestedDuration::Maximum);
+        }
+        parse_duration(value)
+            .map(RequestedDuration::Length)
+            .ok_or_else(|| format!("'{}' is not a duration, example `30m`, `1h30m`, `PT1H` or `max`", value.trim()))
+    }
+}
+
+/// Policies fetched at the same time, one is fetched for every eligible role when the cache is refreshed
+const MAX_CONCURRENT_POLICY_REQUESTS: usize = 8;
+
+/// Requirements of the role management policy that applies when activating a role
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Policy {
+    // ISO8601 duration
+    pub maximum_duration: Option<String>,
+    pub justification_required: bool,
+    pub ticket_required: bool,
+    pub mfa_required: bool,
+    pub approval_required: bool,
+}
+
+impl Policy {
+    pub fn from_rules(rules: &[PolicyRule]) -> Policy {
+        let rule = |id: &str| rules.iter().find(|rule| rule.id == id);
+        let enabled_rules = rule("Enablement_EndUser_Assignment").and_then(|rule| rule.enabled_rules.as_ref());
+        let enabled = |name: &str| enabled_rules.is_some_and(|rules| rules.iter().any(|rule| rule == name));
+        Policy {
+            maximum_duration: rule("Expiration_EndUser_Assignment").and_then(|rule| rule.maximum_duration.clone()),
+            justification_required: enabled("Justification"),
+            ticket_required: enabled("Ticketing"),
+            mfa_required: enabled("MultiFactorAuthentication"),
+            approval_required: rule("Approval_EndUser_Assignment")
+                .and_then(|rule| rule.setting.as_ref())
+                .is_some_and(|setting| setting.is_approval_required),
+        }
+    }
+
+    fn maximum(&self) -> Option<Duration> {
+        self.maximum_duration.as_deref().and_then(parse_iso_duration)
+    }
+
+    /// Short description of each requirement, like `max PT8H` or `ticket`
+    pub fn requirements(&self) -> Vec<String> {
+        let mut requirements = Vec::new();
+        if let Some(maximum_duration) = &self.maximum_duration {
+            requirements.push(format!("max {}", maximum_duration));
+        }
+        [
+            (self.justification_required, "justification"),
+            (self.ticket_required, "ticket"),
+            (self.mfa_required, "MFA"),
+            (self.approval_required, "approval"),
+        ].into_iter()
+            .filter(|(required, _)| *required)
+            .for_each(|(_, requirement)| requirements.push(requirement.to_owned()));
+        requirements
+    }
+
+    /// Reason the duration would be rejected by this policy, if any
+    pub fn duration_violation(&self, duration: &str) -> Option<String> {
+        let Some(requested) = parse_iso_duration(duration) else {
+            return Some(format!("'{}' is not an ISO8601 duration, example `PT30M` or `PT1H`", duration));
+        };
+        match (self.maximum(), &self.maximum_duration) {
+            (Some(maximum), Some(maximum_duration)) if requested > maximum => {
+                Some(format!("{} is longer than the maximum duration {}", duration, maximum_duration))
+            }
+            _ => None,
+        }
+    }
+
+    /// The ISO8601 duration to request, clamped to the maximum duration with a warning if it is longer
+    pub fn duration(&self, requested: RequestedDuration) -> Result<(String, Option<String>), String> {
+        match (requested, self.maximum(), &self.maximum_duration) {
+            (RequestedDuration::Maximum, Some(_), Some(maximum_duration)) => Ok((maximum_duration.to_owned(), None)),
+            (RequestedDuration::Maximum, _, _) => Err("the maximum duration is unknown, give a duration".to_owned()),
+            (RequestedDuration::Length(length), Some(maximum), Some(maximum_duration)) if length > maximum => Ok((
+                maximum_duration.to_owned(),
+                Some(format!("{} is longer than the maximum duration, requesting {}", format_iso_duration(length), maximum_duration)),
+            )),
+            (RequestedDuration::Length(length), _, _) => Ok((format_iso_duration(length), None)),
+        }
+    }
+
+    /// Reasons the activation would be rejected by this policy
+    pub fn violations(&self, activation: &Activation) -> Vec<String> {
+        let mut violations = Vec::new();
+        violations.extend(self.duration_violation(&activation.duration));
+        if self.justification_required && activation.reason.trim().is_empty() {
+            violations.push("a justification is required".to_owned());
+        }
+        if self.ticket_required && activation.ticket.as_ref().is_none_or(|ticket| ticket.number.trim().is_empty()) {
+            violations.push("a ticket is required, use --ticket-number".to_owned());
+        }
+        violations
+    }
+}
+
+/// Fetches the policy of every role concurrently and attaches it to the role. Policies that failed to load are left unset,
+/// as in caches written before policies were fetched, so they are fetched again when activating
+pub async fn attach_policies<T, F>(infos: Vec<T>, fetch: impl Fn(&T) -> F, attach: impl Fn(T, Option<Policy>) -> T) -> Vec<T>
+where
+    F: Future<Output = Result<Policy, String>>,
+{
+    let policies = stream::iter(infos.iter().map(fetch))
+        .buffered(MAX_CONCURRENT_POLICY_REQUESTS)
+        .collect::<Vec<_>>()
+        .await;
+    infos.into_iter()
+        .zip(policies)
+        .map(|(info, policy)| attach(info, policy.ok()))
+        .collect()
+}
+
+/// Policy of each role, warning about the roles whose policy can not be read and treating their requirements as unknown,
+/// so Azure is left to reject activations that do not meet them
+pub async fn policies(pims: &[&dyn Pim<'_>]) -> Vec<Policy> {
+    stream::iter(pims.iter().map(|pim| async move {
+        pim.policy().await.unwrap_or_else(|error| {
+            eprintln!("Warning: the requirements of {} are unknown, failed to read its policy: {}", pim.name(), error);
+            Policy::default()
+        })
+    })).buffered(MAX_CONCURRENT_POLICY_REQUESTS).collect().await
+}
+
+/// Duration of each role, its override if it has one or the default, printing a warning for each that is clamped
+pub fn durations(
+    pims: &[&dyn Pim],
+    policies: &[Policy],
+    default: RequestedDuration,
+    overrides: &[Option<RequestedDuration>],
+) -> Result<Vec<String>, String> {
+    let mut errors = Vec::new();
+    let durations = pims.iter()
+        .zip(policies)
+        .enumerate()
+        .map(|(index, (pim, policy))| {
+            let requested = overrides.get(index).copied().flatten().unwrap_or(default);
+            match policy.duration(requested) {
+                Ok((duration, warning)) => {
+                    if let Some(warning) = warning {
+                        println!("{}: {}", pim.name(), warning);
+                    }
+                    duration
+                }
+                Err(error) => {
+                    errors.push(format!("{}: {}", pim.name(), error));
+                    String::new()
+                }
+            }
+        })
+        .collect();
+    if errors.is_empty() {
+        Ok(durations)
+    } else {
+        Err(errors.join("\n"))
+    }
+}
+
+/// Checks the activation of each role against its policy, listing each role that would reject it
+pub fn check(requests: &[(&dyn Pim, Activation)], policies: &[Policy]) -> Result<(), String> {
+    let violations = requests.iter()
+        .zip(policies)
+        .flat_map(|((pim, activation), policy)| {
+            policy.violations(activation)
+                .into_iter()
+                .map(|violation| format!("{}: {}", pim.name(), violation))
+        })
+        .collect::<Vec<String>>();
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(violations.join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use crate::azure::{Activation, Ticket};
+    use crate::cmd::pim::test_pim::TestPim;
+    use crate::cmd::pim::Pim;
+
+    use super::{check, durations, Policy, RequestedDuration};
+
+    fn maximum(maximum_duration: &str) -> Policy {
+        Policy {
+            maximum_duration: Some(maximum_duration.to_owned()),
+            ..Policy::default()
+        }
+    }
+
+    #[test]
+    fn parses_requested_durations() {
+        assert!(matches!(RequestedDuration::parse(" Max "), Ok(RequestedDuration::Maximum)));
+        assert!(matches!(RequestedDuration::parse("1h30m"), Ok(RequestedDuration::Length(length)) if length == Duration::minutes(90)));
+        assert!(RequestedDuration::parse("PT90").is_err());
+        assert!(RequestedDuration::parse("0m").is_err());
+    }
+
+    #[test]
+    fn clamps_durations_to_the_maximum() {
+        let policy = maximum("PT8H");
+        assert_eq!(policy.duration(RequestedDuration::Length(Duration::hours(1))).unwrap(), ("PT1H".to_owned(), None));
+        assert_eq!(policy.duration(RequestedDuration::Maximum).unwrap(), ("PT8H".to_owned(), None));
+        let (duration, warning) = policy.duration(RequestedDuration::Length(Duration::hours(10))).unwrap();
+        assert_eq!(duration, "PT8H");
+        assert!(warning.is_some());
+    }
+
+    #[test]
+    fn requires_a_duration_when_the_maximum_is_unknown() {
+        assert!(Policy::default().duration(RequestedDuration::Maximum).is_err());
+        assert_eq!(Policy::default().duration(RequestedDuration::Length(Duration::hours(10))).unwrap().0, "PT10H");
+    }
+
+    #[test]
+    fn clamps_each_role_to_its_own_maximum() {
+        let pims = [TestPim::new("reader"), TestPim::new("owner"), TestPim::new("admin")];
+        let pims = pims.iter().map(|pim| pim as &dyn Pim).collect::<Vec<_>>();
+        let policies = [maximum("PT8H"), maximum("PT1H"), maximum("PT2H")];
+        let overrides = [None, None, Some(RequestedDuration::Maximum)];
+        let durations = durations(&pims, &policies, RequestedDuration::Length(Duration::hours(4)), &overrides).unwrap();
+        assert_eq!(durations, ["PT4H", "PT1H", "PT2H"]);
+    }
+
+    fn activation(duration: &str, reason: &str, ticket: Option<Ticket>) -> Activation {
+        Activation {
+            reason: reason.to_owned(),
+            duration: duration.to_owned(),
+            start: None,
+            ticket,
+        }
+    }
+
+    #[test]
+    fn accepts_activations_meeting_the_policy() {
+        let policy = Policy {
+            maximum_duration: Some("PT8H".to_owned()),
+            justification_required: true,
+            ticket_required: true,
+            ..Policy::default()
+        };
+        let ticket = Ticket { number: "CHG-1".to_owned(), system: "Jira".to_owned() };
+        assert!(policy.violations(&activation("PT8H", "Deploy", Some(ticket))).is_empty());
+    }
+
+    #[test]
+    fn lists_every_requirement_an_activation_does_not_meet() {
+        let policy = Policy {
+            maximum_duration: Some("PT1H".to_owned()),
+            justification_required: true,
+            ticket_required: true,
+            ..Policy::default()
+        };
+        assert_eq!(policy.violations(&activation("PT2H", " ", None)).len(), 3);
+        assert_eq!(policy.violations(&activation("1h", "Deploy", None)).len(), 2);
+        let blank = Ticket { number: " ".to_owned(), system: "Jira".to_owned() };
+        assert_eq!(policy.violations(&activation("PT1H", "Deploy", Some(blank))).len(), 1);
+    }
+
+    #[test]
+    fn reports_the_roles_that_would_reject_their_activation() {
+        let reader = TestPim::new("reader");
+        let owner = TestPim::new("owner");
+        let requests = [
+            (&reader as &dyn Pim, activation("PT2H", "Deploy", None)),
+            (&owner as &dyn Pim, activation("PT2H", "Deploy", None)),
+        ];
+        let policies = [maximum("PT8H"), maximum("PT1H")];
+        let violations = check(&requests, &policies).unwrap_err();
+        assert!(violations.starts_with("owner: "));
+        assert!(!violations.contains("reader"));
+        assert!(check(&requests[..1], &policies[..1]).is_ok());
+    }
+}
@@ -0,0 +1,86 @@
+use clap::Args;
+use futures::future::join_all;
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+use crate::azure::Activation;
+use crate::cmd::pim::cache::Cache;
+use crate::cmd::pim::config::Config;
+use crate::cmd::pim::schedule::{format_iso_duration, parse_duration};
+use crate::cmd::pim::selection::parse_selection;
+use crate::cmd::pim::{eligibility_ends_in, format_local, prompt, Clients, Pims};
+
+#[derive(Args)]
+pub struct RenewArgs {
+    #[arg(short, long, help = "Fetch updated cache roles from Azure")]
+    refresh: bool,
+    #[arg(long, help = "Show eligibilities ending within this number of days, defaults to the expiry warning days")]
+    days: Option<i64>,
+}
+
+/// Requests extension of eligibilities nearing their end, or renewal of the ones that have ended
+pub async fn renew(args: &RenewArgs, config: &Config) {
+    let cache = Cache {
+        refresh: args.refresh,
+        ttl: config.cache_ttl(),
+    };
+    let clients = Clients::new(config);
+    let pims = Pims::load(&clients, &cache, config, false).await;
+    pims.report_errors();
+    let days = args.days.unwrap_or(config.expiry_warning_days);
+    let expiring = pims.expiring(days);
+
+    if expiring.is_empty() {
+        println!("You have no eligibilities ending within {} days", days);
+        return;
+    }
+
+    expiring.iter().enumerate().for_each(|(index, pim)| {
+        let end = pim.eligibility_end().unwrap();
+        println!("{}.\t[{}] {} (until {}, {})", index + 1, pim.source(), pim.name(), format_local(&end), eligibility_ends_in(&end));
+    });
+
+    let reader = BufReader::new(tokio::io::stdin());
+    let mut lines = reader.lines();
+    let selected = loop {
+        let selection = prompt(&mut lines, "Please select eligibilities to renew separated by ',': ").await;
+        match parse_selection(&selection, expiring.len()) {
+            Ok(indexes) => break indexes.into_iter().map(|index| expiring[index]).collect::<Vec<_>>(),
+            Err(error) => println!("{}", error),
+        }
+    };
+    let duration = loop {
+        let duration = prompt(&mut lines, "Extend the eligibility by, example `90d` or `P90D`, leave empty for `P365D`: ").await;
+        if duration.is_empty() {
+            break "P365D".to_owned();
+        }
+        match parse_duration(&duration) {
+            Some(duration) => break format_iso_duration(duration),
+            None => println!("'{}' is not a duration, example `90d` or `P90D`", duration),
+        }
+    };
+    let reason = loop {
+        let reason = prompt(&mut lines, "Reason: ").await;
+        if reason.is_empty() {
+            println!("A justification is required to renew an eligibility");
+            continue;
+        }
+        break reason;
+    };
+
+    let requests = join_all(selected.iter().map(|pim| {
+        pim.renew(Activation {
+            reason: reason.clone(),
+            duration: duration.clone(),
+            start: None,
+            ticket: None,
+        })
+    })).await;
+    // The cached eligibilities still end when they did before the renewal
+    selected.iter()
+        .zip(&requests)
+        .filter(|(_, request_id)| request_id.is_some())
+        .for_each(|(pim, _)| Cache::invalidate(pim.source()));
+    if requests.iter().any(|request_id| request_id.is_none()) {
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,200 @@
+use std::future::join;
+
+use chrono::{Duration, Utc};
+use clap::Args;
+use futures::future::join_all;
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+use crate::azure::{graph, management};
+use crate::cmd::pim::config::Config;
+use crate::cmd::pim::selection::parse_selection;
+use crate::cmd::pim::{graph_expiration, loaded, local_time, parse_time, prompt, requested_duration, Source};
+
+#[derive(Args)]
+pub struct RequestsArgs {
+    #[arg(long, default_value_t = 7, help = "Show requests made within this number of days, pending requests are always shown")]
+    days: i64,
+}
+
+/// A request made by the signed-in user
+struct MyRequest {
+    source: Source,
+    id: String,
+    // Only set for Azure roles, which are cancelled at the scope they were requested for
+    scope: String,
+    role: String,
+    resource: String,
+    action: String,
+    status: String,
+    justification: String,
+    duration: String,
+    submitted: String,
+}
+
+impl MyRequest {
+    fn from_group_request(request: graph::ScheduleRequest) -> MyRequest {
+        let (duration, end) = graph_expiration(&request.schedule_info);
+        MyRequest {
+            source: Source::Group,
+            id: request.id,
+            scope: String::new(),
+            role: request.access_id.unwrap_or_default(),
+            resource: request.group.and_then(|group| group.display_name).unwrap_or_default(),
+            action: action(request.action.as_deref()),
+            status: request.status,
+            justification: request.justification.unwrap_or_default(),
+            duration: requested_duration(duration, end),
+            submitted: request.created_date_time,
+        }
+    }
+
+    fn from_directory_role_request(request: graph::ScheduleRequest) -> MyRequest {
+        let (duration, end) = graph_expiration(&request.schedule_info);
+        MyRequest {
+            source: Source::EntraRole,
+            id: request.id,
+            scope: String::new(),
+            role: request.role_definition.and_then(|role_definition| role_definition.display_name).unwrap_or_default(),
+            resource: match request.directory_scope.and_then(|scope| scope.display_name) {
+                Some(scope) => scope,
+                None => "Directory".to_owned(),
+            },
+            action: action(request.action.as_deref()),
+            status: request.status,
+            justification: request.justification.unwrap_or_default(),
+            duration: requested_duration(duration, end),
+            submitted: request.created_date_time,
+        }
+    }
+
+    fn from_role_request(request: management::ScheduleRequest) -> MyRequest {
+        let properties = request.properties;
+        let (role, resource) = properties.expanded_properties
+            .map(|expanded| (expanded.role_definition.display_name, expanded.scope.display_name))
+            .unwrap_or_default();
+        let (duration, end) = properties.schedule_info
+            .and_then(|schedule| schedule.expiration)
+            .map(|expiration| (expiration.duration, expiration.end_date_time))
+            .unwrap_or_default();
+        MyRequest {
+            source: Source::AzureRole,
+            id: request.name,
+            scope: properties.scope.unwrap_or_default(),
+            role,
+            resource,
+            action: action(properties.request_type.as_deref()),
+            status: properties.status,
+            justification: properties.justification.unwrap_or_default(),
+            duration: requested_duration(duration, end),
+            submitted: properties.created_on.unwrap_or_default(),
+        }
+    }
+
+    /// Requests waiting for approval or for their scheduled start can still be cancelled, other pending statuses like
+    /// `PendingProvisioning` are already being carried out
+    fn is_pending(&self) -> bool {
+        matches!(self.status.as_str(), "PendingApproval" | "PendingScheduleCreation" | "ScheduleCreated")
+    }
+
+    async fn cancel(&self, graph_client: &graph::GraphClient, management_client: &management::ManagementClient) -> Result<(), String> {
+        match self.source {
+            Source::Group => graph_client.cancel_group_request(&self.id).await,
+            Source::EntraRole => graph_client.cancel_directory_role_request(&self.id).await,
+            Source::AzureRole => management_client.cancel_request(&self.scope, &self.id).await,
+        }
+    }
+}
+
+/// What the request asks for, Graph and ARM name the same request types with different casing
+fn action(request_type: Option<&str>) -> String {
+    match request_type {
+        Some("SelfActivate" | "selfActivate") => "activate".to_owned(),
+        Some("SelfDeactivate" | "selfDeactivate") => "deactivate".to_owned(),
+        Some("SelfExtend" | "selfExtend") => "extend".to_owned(),
+        Some("SelfRenew" | "selfRenew") => "renew".to_owned(),
+        Some(request_type) => request_type.to_owned(),
+        None => "request".to_owned(),
+    }
+}
+
+pub async fn requests(args: &RequestsArgs, config: &Config) {
+    let graph_client = graph::GraphClient::new(&config.endpoints.graph);
+    let management_client = management::ManagementClient::new(&config.endpoints.management);
+
+    let (group_requests, directory_role_requests, role_requests) = join!(
+        graph_client.get_my_group_requests(),
+        graph_client.get_my_directory_role_requests(),
+        management_client.get_requests("")
+    ).await;
+    let mut errors = Vec::new();
+    let group_requests = loaded(Source::Group, group_requests, &mut errors);
+    let directory_role_requests = loaded(Source::EntraRole, directory_role_requests, &mut errors);
+    let role_requests = loaded(Source::AzureRole, role_requests, &mut errors);
+    errors.iter().for_each(|(source, error)| eprintln!("[{}] Failed to load your requests: {}", source, error));
+
+    let since = Utc::now() - Duration::days(args.days);
+    let mut requests = group_requests.into_iter()
+        .map(MyRequest::from_group_request)
+        .chain(directory_role_requests.into_iter().map(MyRequest::from_directory_role_request))
+        .chain(role_requests.into_iter().map(MyRequest::from_role_request))
+        .filter(|request| request.is_pending() || parse_time(&request.submitted).is_some_and(|submitted| submitted >= since))
+        .collect::<Vec<MyRequest>>();
+    requests.sort_by_key(|request| std::cmp::Reverse(parse_time(&request.submitted)));
+
+    if requests.is_empty() {
+        if !errors.is_empty() {
+            std::process::exit(1);
+        }
+        println!("You have made no requests in the last {} days", args.days);
+        return;
+    }
+
+    let reader = BufReader::new(tokio::io::stdin());
+    let mut lines = reader.lines();
+    loop {
+        requests.iter().enumerate().for_each(|(index, request)| {
+            println!(
+                "{}.\t[{}] {} {} on {}: {}",
+                index + 1,
+                request.source,
+                request.action,
+                request.role,
+                request.resource,
+                request.status
+            );
+            println!("\tDuration: {}, submitted {}", request.duration, local_time(&request.submitted));
+            println!("\tJustification: {}", request.justification);
+        });
+
+        if !requests.iter().any(|request| request.is_pending()) {
+            return;
+        }
+        let selection = prompt(&mut lines, "Select pending request(s) to cancel separated by ',', leave empty to quit: ").await;
+        if selection.is_empty() {
+            return;
+        }
+        let selected = match parse_selection(&selection, requests.len()) {
+            Ok(indexes) if indexes.iter().all(|index| requests[*index].is_pending()) => indexes,
+            Ok(_) => {
+                println!("Only requests waiting for approval or for their start can be cancelled");
+                continue;
+            }
+            Err(error) => {
+                println!("{}", error);
+                continue;
+            }
+        };
+
+        let results = join_all(selected.iter().map(|index| requests[*index].cancel(&graph_client, &management_client))).await;
+        selected.into_iter().zip(results).for_each(|(index, result)| {
+            let request = &mut requests[index];
+            match result {
+                Ok(()) => {
+                    println!("Cancelled request for {} on {}", request.role, request.resource);
+                    request.status = "Canceled".to_owned();
+                }
+                Err(error) => println!("Failed to cancel request for {} on {}: {}", request.role, request.resource, error),
+            }
+        });
+    }
+}
@@ -1,21 +1,133 @@
-use std::future::Future;
+use std::collections::HashMap;
+use std::future::{Future, join};
 use std::pin::Pin;
 
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
-use crate::azure::management;
-use crate::cmd::pim::Pim;
+use crate::azure::{management, Activation};
+use crate::cmd::pim::{parse_time, submitted, ActiveAssignment, ActiveAssignments, Pim, Source};
+use crate::cmd::pim::policy::{attach_policies, Policy};
 
-pub async fn fetch_role_info(management_client: &management::ManagementClient) -> Vec<RoleInfo> {
-    management_client.get_available_roles().await
+pub async fn fetch_role_info(management_client: &management::ManagementClient) -> Result<Vec<RoleInfo>, String> {
+    let (roles, entities, subscriptions) = join!(
+        management_client.get_available_roles(),
+        management_client.get_entities(),
+        management_client.get_subscriptions()
+    ).await;
+    // Names are only used for display, so scopes the user cannot read are shown by their id
+    let scope_names = ScopeNames::new(entities.unwrap_or_default(), subscriptions.unwrap_or_default());
+    let role_info = roles?
         .into_iter()
-        .map(|role| RoleInfo {
-            role_name: role.properties.expanded_properties.role_definition.display_name,
-            scope_name: role.properties.expanded_properties.scope.display_name,
-            scope: role.properties.expanded_properties.scope.id,
-            role_definition_id: role.properties.role_definition_id,
+        .map(|role| {
+            let properties = role.properties;
+            let expanded = properties.expanded_properties;
+            RoleInfo {
+                scope_path: scope_names.path(&expanded.scope.id, &expanded.scope.display_name),
+                role_name: expanded.role_definition.display_name,
+                scope_name: expanded.scope.display_name,
+                scope: expanded.scope.id,
+                role_definition_id: properties.role_definition_id,
+                eligibility_schedule_id: properties.role_eligibility_schedule_id,
+                // The principal of an eligibility granted through a group is the group
+                granting_group: expanded.principal
+                    .filter(|_| properties.member_type.as_deref() == Some("Group"))
+                    .map(|principal| principal.display_name),
+                member_type: properties.member_type,
+                eligibility_end: properties.end_date_time,
+                policy: None,
+            }
         })
-        .collect()
+        .collect::<Vec<RoleInfo>>();
+
+    Ok(attach_policies(
+        role_info,
+        |info| fetch_policy(management_client, info.scope.to_owned(), info.role_definition_id.to_owned()),
+        |info, policy| RoleInfo { policy, ..info },
+    ).await)
+}
+
+async fn fetch_policy(management_client: &management::ManagementClient, scope: String, role_definition_id: String) -> Result<Policy, String> {
+    Ok(Policy::from_rules(&management_client.get_policy_rules(&scope, &role_definition_id).await?))
+}
+
+pub async fn fetch_active_assignments(management_client: &management::ManagementClient) -> Result<ActiveAssignments, String> {
+    Ok(management_client.get_active_roles().await?
+        .into_iter()
+        .filter(|role| role.properties.assignment_type.as_deref() == Some("Activated"))
+        .map(|role| (
+            assignment_key(&role.properties.scope, &role.properties.role_definition_id),
+            ActiveAssignment::new(role.properties.start_date_time, role.properties.end_date_time),
+        ))
+        .collect())
+}
+
+/// Display names of the management groups and subscriptions that scopes are nested in
+struct ScopeNames {
+    // Keyed by lowercase id, like `/subscriptions/{id}`
+    names: HashMap<String, String>,
+    // Management group each subscription belongs to, keyed by lowercase subscription id
+    parents: HashMap<String, String>,
+}
+
+impl ScopeNames {
+    fn new(entities: Vec<management::Entity>, subscriptions: Vec<management::Subscription>) -> ScopeNames {
+        let mut names = HashMap::new();
+        let mut parents = HashMap::new();
+        entities.into_iter().for_each(|entity| {
+            let id = if entity.entity_type == "/subscriptions" {
+                format!("/subscriptions/{}", entity.name)
+            } else {
+                entity.id
+            };
+            if let Some(parent) = entity.properties.parent.and_then(|parent| parent.id) {
+                parents.insert(id.to_lowercase(), parent.to_lowercase());
+            }
+            names.insert(id.to_lowercase(), entity.properties.display_name.unwrap_or(entity.name));
+        });
+        subscriptions.into_iter().for_each(|subscription| {
+            names.insert(format!("/subscriptions/{}", subscription.subscription_id).to_lowercase(), subscription.display_name);
+        });
+        ScopeNames { names, parents }
+    }
+
+    fn name(&self, id: &str) -> String {
+        self.names.get(&id.to_lowercase())
+            .cloned()
+            .unwrap_or_else(|| id.rsplit('/').next().unwrap_or_default().to_owned())
+    }
+
+    /// Levels of the tree a scope is shown in, from its management group down to the scope itself,
+    /// like `["Management group Platform", "Subscription Prod", "Resource group web"]`
+    fn path(&self, scope: &str, scope_name: &str) -> Vec<String> {
+        let segments = scope.trim_start_matches('/').split('/').collect::<Vec<&str>>();
+        match segments.as_slice() {
+            ["providers", "Microsoft.Management", "managementGroups", _] => vec![format!("Management group {}", scope_name)],
+            ["subscriptions", subscription, rest @ ..] => {
+                let subscription_id = format!("/subscriptions/{}", subscription);
+                let mut path = Vec::new();
+                path.extend(self.parents.get(&subscription_id.to_lowercase())
+                    .map(|parent| format!("Management group {}", self.name(parent))));
+                path.push(format!("Subscription {}", self.name(&subscription_id)));
+                match rest {
+                    [] => {}
+                    ["resourceGroups", resource_group] => path.push(format!("Resource group {}", resource_group)),
+                    ["resourceGroups", resource_group, .., resource_type, _] => {
+                        path.push(format!("Resource group {}", resource_group));
+                        path.push(format!("{} ({})", scope_name, resource_type));
+                    }
+                    _ => path.push(scope_name.to_owned()),
+                }
+                path
+            }
+            _ => vec![scope_name.to_owned()],
+        }
+    }
+}
+
+/// ARM does not guarantee the casing of ids, so they are compared in lowercase
+fn assignment_key(scope: &str, role_definition_id: &str) -> (String, String) {
+    (scope.to_lowercase(), role_definition_id.to_lowercase())
 }
 
 #[derive(Serialize, Deserialize)]
@@ -24,51 +136,248 @@ pub struct RoleInfo {
     scope_name: String,
     scope: String,
     role_definition_id: String,
+    #[serde(default)]
+    eligibility_schedule_id: Option<String>,
+    // `Direct`, `Group` or `Inherited` from a parent scope
+    #[serde(default)]
+    member_type: Option<String>,
+    // Levels of the scope tree, missing in caches written before scope names were resolved
+    #[serde(default)]
+    scope_path: Vec<String>,
+    // The group the eligibility is granted to, if it is not granted to the user directly
+    #[serde(default)]
+    granting_group: Option<String>,
+    #[serde(default)]
+    eligibility_end: Option<String>,
+    #[serde(default)]
+    policy: Option<Policy>,
+}
+
+impl RoleInfo {
+    pub fn assignment_key(&self) -> (String, String) {
+        assignment_key(&self.scope, &self.role_definition_id)
+    }
+
+    /// Suffix telling which group grants the eligibility, like ` via group admins`
+    fn via(&self) -> String {
+        self.granting_group.as_ref()
+            .map(|group| format!(" via group {}", group))
+            .unwrap_or_default()
+    }
 }
 
 pub struct RolePim<'a> {
     management_client: &'a management::ManagementClient,
     role_info: RoleInfo,
+    active: Option<ActiveAssignment>,
 }
 
 impl<'a> RolePim<'a> {
-    pub fn new(management_client: &'a management::ManagementClient, role_info: RoleInfo) -> RolePim<'a> {
+    /// Levels of the scope tree the role is shown in, ending with the scope itself
+    pub fn scope_path(&self) -> Vec<String> {
+        if self.role_info.scope_path.is_empty() {
+            vec![self.role_info.scope_name.to_owned()]
+        } else {
+            self.role_info.scope_path.clone()
+        }
+    }
+
+    /// Name of the role at its scope in the scope tree, like `Contributor via group admins`
+    pub fn role_label(&self) -> String {
+        format!("{}{}", self.role_info.role_name, self.role_info.via())
+    }
+
+    pub fn new(management_client: &'a management::ManagementClient, role_info: RoleInfo, active: Option<ActiveAssignment>) -> RolePim<'a> {
         RolePim {
             management_client,
             role_info,
+            active,
         }
     }
 }
 
 
 impl<'a> Pim<'a> for RolePim<'a> {
+    fn source(&self) -> Source {
+        Source::AzureRole
+    }
+
     fn group_by(&self) -> String {
         self.role_info.role_name.to_owned()
     }
 
     fn resource_id(&self) -> String {
-        self.role_info.scope_name.to_owned()
+        format!("{}{}", self.role_info.scope_name, self.role_info.via())
+    }
+
+    fn name(&self) -> String {
+        format!("{} ({}){}", self.role_info.scope_name, self.role_info.role_name, self.role_info.via())
+    }
+
+    fn selectors(&self) -> Vec<String> {
+        let mut selectors = vec![
+            self.name(),
+            format!("{} ({})", self.role_info.scope_name, self.role_info.role_name),
+            self.role_info.scope_name.to_owned(),
+            self.role_info.scope.to_owned(),
+            self.role_info.role_name.to_owned(),
+        ];
+        selectors.extend(self.role_info.eligibility_schedule_id.clone());
+        selectors
     }
 
     fn ensure_token(&self) -> Pin<Box<dyn Future<Output=()> + 'a>> {
         Box::pin(self.management_client.ensure_token())
     }
 
-    fn activate(&self, reason: String, duration: String) -> Pin<Box<dyn Future<Output=()> + 'a>> {
+    fn activate(&self, activation: Activation) -> Pin<Box<dyn Future<Output=Option<String>> + 'a>> {
+        let management_client = self.management_client;
+        let name = self.name();
         let scope = self.role_info.scope.clone();
         let role_definition_id = self.role_info.role_definition_id.clone();
         let role_assignment_id = uuid::Uuid::new_v4().to_string();
-        Box::pin(async {
-            println!(
-                "{}",
-                self.management_client.activate_role(
-                    reason,
-                    duration,
-                    scope,
-                    role_definition_id,
-                    role_assignment_id,
-                ).await
-            );
+        let eligibility_schedule_id = self.role_info.eligibility_schedule_id.clone();
+        Box::pin(async move {
+            let result = management_client.activate_role(
+                activation,
+                scope,
+                role_definition_id,
+                role_assignment_id,
+                eligibility_schedule_id,
+            ).await;
+            submitted(name, result.map(|request| (request.name, request.properties.status)))
+        })
+    }
+
+    fn request_status(&self, request_id: String) -> Pin<Box<dyn Future<Output=Result<String, String>> + 'a>> {
+        let management_client = self.management_client;
+        let scope = self.role_info.scope.clone();
+        Box::pin(async move {
+            management_client.get_role_assignment_request(&scope, &request_id).await.map(|request| request.properties.status)
         })
     }
+
+    fn active(&self) -> Option<&ActiveAssignment> {
+        self.active.as_ref()
+    }
+
+    fn deactivate(&self) -> Pin<Box<dyn Future<Output=Option<String>> + 'a>> {
+        let management_client = self.management_client;
+        let name = self.name();
+        let scope = self.role_info.scope.clone();
+        let role_definition_id = self.role_info.role_definition_id.clone();
+        let role_assignment_id = uuid::Uuid::new_v4().to_string();
+        Box::pin(async move {
+            let result = management_client.deactivate_role(scope, role_definition_id, role_assignment_id).await;
+            submitted(name, result.map(|request| (request.name, request.properties.status)))
+        })
+    }
+
+    fn policy(&self) -> Pin<Box<dyn Future<Output=Result<Policy, String>> + 'a>> {
+        let management_client = self.management_client;
+        let cached = self.role_info.policy.clone();
+        let scope = self.role_info.scope.clone();
+        let role_definition_id = self.role_info.role_definition_id.clone();
+        Box::pin(async move {
+            match cached {
+                Some(policy) => Ok(policy),
+                None => fetch_policy(management_client, scope, role_definition_id).await,
+            }
+        })
+    }
+
+    fn cached_policy(&self) -> Option<&Policy> {
+        self.role_info.policy.as_ref()
+    }
+
+    fn last_reason(&self) -> Pin<Box<dyn Future<Output=Option<String>> + 'a>> {
+        let management_client = self.management_client;
+        let name = self.name();
+        let (scope, role_definition_id) = self.role_info.assignment_key();
+        Box::pin(async move {
+            management_client.get_requests(&scope).await
+                .map_err(|error| eprintln!("Warning: failed to read the previous requests for {}: {}", name, error))
+                .ok()?
+                .into_iter()
+                .filter(|request| request.properties.request_type.as_deref() == Some("SelfActivate"))
+                .filter(|request| {
+                    let properties = &request.properties;
+                    properties.scope.as_ref().zip(properties.role_definition_id.as_ref())
+                        .is_some_and(|(request_scope, request_role)| assignment_key(request_scope, request_role) == (scope.to_owned(), role_definition_id.to_owned()))
+                })
+                .max_by(|a, b| a.properties.created_on.cmp(&b.properties.created_on))
+                .and_then(|request| request.properties.justification)
+        })
+    }
+
+    fn eligibility_end(&self) -> Option<DateTime<Utc>> {
+        self.role_info.eligibility_end.as_deref().and_then(parse_time)
+    }
+
+    fn renew(&self, activation: Activation) -> Pin<Box<dyn Future<Output=Option<String>> + 'a>> {
+        let management_client = self.management_client;
+        let name = self.name();
+        let scope = self.role_info.scope.clone();
+        let role_definition_id = self.role_info.role_definition_id.clone();
+        let expired = self.eligibility_end().is_some_and(|end| end <= Utc::now());
+        Box::pin(async move {
+            let result = management_client.renew_role(activation, scope, role_definition_id, expired).await;
+            submitted(name, result.map(|request| (request.name, request.properties.status)))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::ScopeNames;
+
+    fn scope_names() -> ScopeNames {
+        ScopeNames {
+            names: HashMap::from([
+                ("/providers/microsoft.management/managementgroups/platform".to_owned(), "Platform".to_owned()),
+                ("/subscriptions/1111".to_owned(), "Prod".to_owned()),
+            ]),
+            parents: HashMap::from([
+                ("/subscriptions/1111".to_owned(), "/providers/microsoft.management/managementgroups/platform".to_owned()),
+            ]),
+        }
+    }
+
+    #[test]
+    fn shows_management_groups_at_the_root() {
+        let path = scope_names().path("/providers/Microsoft.Management/managementGroups/platform", "Platform");
+        assert_eq!(path, ["Management group Platform"]);
+    }
+
+    #[test]
+    fn nests_subscriptions_in_their_management_group() {
+        assert_eq!(scope_names().path("/subscriptions/1111", "Prod"), ["Management group Platform", "Subscription Prod"]);
+    }
+
+    #[test]
+    fn nests_resource_groups_and_resources_in_their_subscription() {
+        let names = scope_names();
+        assert_eq!(
+            names.path("/subscriptions/1111/resourceGroups/web", "web"),
+            ["Management group Platform", "Subscription Prod", "Resource group web"]
+        );
+        assert_eq!(
+            names.path("/subscriptions/1111/resourceGroups/web/providers/Microsoft.Web/sites/shop", "shop"),
+            ["Management group Platform", "Subscription Prod", "Resource group web", "shop (sites)"]
+        );
+    }
+
+    #[test]
+    fn shows_subscriptions_without_a_known_management_group_at_the_root() {
+        assert_eq!(scope_names().path("/subscriptions/2222/resourceGroups/db", "db"), ["Subscription 2222", "Resource group db"]);
+    }
+
+    #[test]
+    fn shows_other_scopes_by_their_name() {
+        let names = scope_names();
+        assert_eq!(names.path("/subscriptions/1111/providers/Microsoft.Security/pricings/vm", "vm"), ["Management group Platform", "Subscription Prod", "vm"]);
+        assert_eq!(names.path("/", "Root"), ["Root"]);
+    }
 }
@@ -0,0 +1,206 @@
+use chrono::{DateTime, Duration, Local, NaiveDateTime, NaiveTime, TimeZone, Utc};
+
+/// Parses the start of an activation, either relative to now like `+2h` or `+1h30m`, a time of day like `02:00`
+/// meaning its next occurrence, or a date and time in local time or RFC3339
+pub fn parse_start(value: &str) -> Result<DateTime<Utc>, String> {
+    let now = Utc::now();
+    let start = if let Some(offset) = value.strip_prefix('+') {
+        parse_offset(offset)
+            .and_then(|offset| now.checked_add_signed(offset))
+            .ok_or_else(|| format!("Invalid relative start '{}', example `+2h` or `+1h30m`", value))?
+    } else if let Ok(start) = DateTime::parse_from_rfc3339(value) {
+        start.with_timezone(&Utc)
+    } else if let Ok(time) = NaiveTime::parse_from_str(value, "%H:%M") {
+        let today = from_local(Local::now().date_naive().and_time(time))?;
+        if today <= now {
+            today + Duration::days(1)
+        } else {
+            today
+        }
+    } else {
+        let start = NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M")
+            .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M"))
+            .map_err(|_| format!("Invalid start '{}', example `+2h`, `02:00` or `2023-06-01 02:00`", value))?;
+        from_local(start)?
+    };
+
+    if start <= now {
+        Err(format!("Start '{}' is in the past", value))
+    } else {
+        Ok(start)
+    }
+}
+
+fn from_local(time: NaiveDateTime) -> Result<DateTime<Utc>, String> {
+    Local.from_local_datetime(&time)
+        .earliest()
+        .map(|time| time.with_timezone(&Utc))
+        .ok_or_else(|| format!("{} does not exist in the local timezone", time))
+}
+
+/// Parses an ISO8601 duration of days, hours, minutes and seconds like `PT30M` or `P1DT2H`
+pub fn parse_iso_duration(value: &str) -> Option<Duration> {
+    let value = value.strip_prefix('P')?;
+    let (date, time) = value.split_once('T').unwrap_or((value, ""));
+    let mut total = Duration::zero();
+    for (part, units) in [(date, "D"), (time, "HMS")] {
+        let mut number = String::new();
+        for c in part.chars() {
+            if c.is_ascii_digit() {
+                number.push(c);
+                continue;
+            }
+            let amount = number.parse::<i64>().ok()?;
+            number.clear();
+            let seconds = match c {
+                'D' if units.contains(c) => 86400,
+                'H' if units.contains(c) => 3600,
+                'M' if units.contains(c) => 60,
+                'S' if units.contains(c) => 1,
+                _ => return None,
+            };
+            total = total.checked_add(&checked_duration(amount, seconds)?)?;
+        }
+        if !number.is_empty() {
+            return None;
+        }
+    }
+    if total.is_zero() {
+        None
+    } else {
+        Some(total)
+    }
+}
+
+/// Parses a duration either in ISO8601 like `PT1H30M`, or as a sum of days, hours and minutes like `1h30m`
+pub fn parse_duration(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if value.starts_with(['P', 'p']) {
+        parse_iso_duration(&value.to_uppercase())
+    } else {
+        parse_offset(&value.to_lowercase())
+    }
+}
+
+/// Formats a duration in ISO8601 like `PT1H30M` or `P1DT2H`, which is what the PIM APIs accept
+pub fn format_iso_duration(duration: Duration) -> String {
+    let seconds = duration.num_seconds();
+    let (days, hours, minutes, seconds) = (seconds / 86400, seconds % 86400 / 3600, seconds % 3600 / 60, seconds % 60);
+    let mut formatted = "P".to_owned();
+    if days > 0 {
+        formatted.push_str(&format!("{}D", days));
+    }
+    if hours > 0 || minutes > 0 || seconds > 0 {
+        formatted.push('T');
+    }
+    [(hours, 'H'), (minutes, 'M'), (seconds, 'S')].into_iter()
+        .filter(|(amount, _)| *amount > 0)
+        .for_each(|(amount, unit)| formatted.push_str(&format!("{}{}", amount, unit)));
+    formatted
+}
+
+/// Parses a sum of days, hours and minutes like `1d`, `2h` or `1h30m`
+fn parse_offset(value: &str) -> Option<Duration> {
+    let mut total = Duration::zero();
+    let mut number = String::new();
+    for c in value.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let amount = number.parse::<i64>().ok()?;
+        number.clear();
+        let seconds = match c {
+            'd' => 86400,
+            'h' => 3600,
+            'm' => 60,
+            _ => return None,
+        };
+        total = total.checked_add(&checked_duration(amount, seconds)?)?;
+    }
+    if number.is_empty() && !total.is_zero() {
+        Some(total)
+    } else {
+        None
+    }
+}
+
+/// A number of units of the given length in seconds, None if it is too long to be represented
+fn checked_duration(amount: i64, seconds: i64) -> Option<Duration> {
+    // Durations are kept in milliseconds
+    amount.checked_mul(seconds)
+        .filter(|seconds| seconds.checked_mul(1000).is_some())
+        .map(Duration::seconds)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, Utc};
+
+    use super::{format_iso_duration, parse_duration, parse_start};
+
+    #[test]
+    fn parses_days_hours_and_minutes() {
+        assert_eq!(parse_duration("1h30m"), Some(Duration::minutes(90)));
+        assert_eq!(parse_duration("2d"), Some(Duration::days(2)));
+        assert_eq!(parse_duration(" 1H "), Some(Duration::hours(1)));
+    }
+
+    #[test]
+    fn parses_iso_durations() {
+        assert_eq!(parse_duration("PT1H30M"), Some(Duration::minutes(90)));
+        assert_eq!(parse_duration("P1DT2H"), Some(Duration::hours(26)));
+        assert_eq!(parse_duration("pt45s"), Some(Duration::seconds(45)));
+    }
+
+    #[test]
+    fn rejects_invalid_and_empty_durations() {
+        assert_eq!(parse_duration("PT90"), None);
+        assert_eq!(parse_duration("P1H"), None);
+        assert_eq!(parse_duration("0m"), None);
+        assert_eq!(parse_duration("PT0M"), None);
+        assert_eq!(parse_duration("90"), None);
+        assert_eq!(parse_duration("1x"), None);
+        assert_eq!(parse_duration(""), None);
+    }
+
+    #[test]
+    fn rejects_durations_too_long_to_represent() {
+        assert_eq!(parse_duration("99999999999999d"), None);
+        assert_eq!(parse_duration("P99999999999999D"), None);
+        assert_eq!(parse_duration("9223372036854775807m"), None);
+        assert_eq!(parse_duration("99999999999d99999999999d"), None);
+    }
+
+    #[test]
+    fn formats_iso_durations() {
+        assert_eq!(format_iso_duration(Duration::minutes(90)), "PT1H30M");
+        assert_eq!(format_iso_duration(Duration::hours(26)), "P1DT2H");
+        assert_eq!(format_iso_duration(Duration::days(365)), "P365D");
+    }
+
+    #[test]
+    fn parses_relative_starts() {
+        let before = Utc::now();
+        let start = parse_start("+1h30m").unwrap();
+        assert!(start >= before + Duration::minutes(90) && start <= Utc::now() + Duration::minutes(90));
+        assert!(parse_start("+0m").is_err());
+        assert!(parse_start("+2").is_err());
+        assert!(parse_start("+99999999999999d").is_err());
+    }
+
+    #[test]
+    fn parses_the_next_occurrence_of_a_time_of_day() {
+        let start = parse_start("02:00").unwrap();
+        assert!(start > Utc::now() && start <= Utc::now() + Duration::days(1));
+        assert!(parse_start("25:00").is_err());
+    }
+
+    #[test]
+    fn rejects_starts_in_the_past() {
+        assert!(parse_start("2020-01-01 02:00").is_err());
+        assert!(parse_start("2020-01-01T02:00:00Z").is_err());
+        assert!(parse_start("3000-01-01T02:00:00Z").is_ok());
+        assert!(parse_start("tomorrow").is_err());
+    }
+}
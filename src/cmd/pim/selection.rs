@@ -0,0 +1,226 @@
+use std::ops::RangeInclusive;
+
+use crate::cmd::pim::{unique, MenuLine, Pim};
+
+/// Selects roles from the menu by a list separated by ',' of numbers like `3`, ranges like `3-7`, header names selecting
+/// every role under the header, or parts of role names like `prod-aks`. Items prefixed with `!` are excluded instead.
+pub fn select<'a>(menu: &[MenuLine<'a>], selection: &str) -> Result<Vec<&'a dyn Pim<'a>>, String> {
+    // Menu index of each entry, in the order they are numbered
+    let entries = menu.iter()
+        .enumerate()
+        .filter(|(_, line)| matches!(line, MenuLine::Entry(..)))
+        .map(|(index, _)| index)
+        .collect::<Vec<usize>>();
+
+    let mut included = vec![false; menu.len()];
+    let mut excluded = vec![false; menu.len()];
+    for item in selection.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+        let (item, target) = match item.strip_prefix('!') {
+            Some(item) => (item.trim(), &mut excluded),
+            None => (item, &mut included),
+        };
+        resolve(menu, &entries, item)?.into_iter().for_each(|index| target[index] = true);
+    }
+    if !included.contains(&true) {
+        return Err("Select at least one role, `!` only excludes roles from the selection".to_owned());
+    }
+
+    // A role can be in the menu twice when it is required by the project, excluding it anywhere excludes it everywhere
+    let excluded_pims = menu.iter()
+        .enumerate()
+        .filter(|(index, _)| excluded[*index])
+        .filter_map(|(_, line)| match line {
+            MenuLine::Entry(_, _, pim) => Some(*pim),
+            MenuLine::Header(..) => None,
+        })
+        .collect::<Vec<_>>();
+    Ok(unique(menu.iter()
+        .enumerate()
+        .filter(|(index, _)| included[*index])
+        .filter_map(|(_, line)| match line {
+            MenuLine::Entry(_, _, pim) => Some(*pim),
+            MenuLine::Header(..) => None,
+        })
+        .filter(|pim| !excluded_pims.iter().any(|excluded| std::ptr::addr_eq(*excluded, *pim)))))
+}
+
+/// Parses a selection of numbers like `3` and ranges like `3-7` separated by ',' into sorted 0-based indexes of `count`
+/// numbered items, for lists that are only selected by number
+pub fn parse_selection(selection: &str, count: usize) -> Result<Vec<usize>, String> {
+    let mut indexes = Vec::new();
+    for item in selection.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+        match parse_range(item, count)? {
+            Some(range) => indexes.extend(range),
+            None => return Err(format!("'{}' is not a number or a range like `3-7`", item)),
+        }
+    }
+    if indexes.is_empty() {
+        return Err(format!("Select at least one number between 1 and {}", count));
+    }
+    indexes.sort_unstable();
+    indexes.dedup();
+    Ok(indexes)
+}
+
+/// 0-based indexes of a number like `3` or a range like `3-7` of `count` numbered items, None if the item is neither
+fn parse_range(item: &str, count: usize) -> Result<Option<RangeInclusive<usize>>, String> {
+    let number = |value: &str| -> Result<Option<usize>, String> {
+        match value.trim().parse::<usize>() {
+            Ok(number) if (1..=count).contains(&number) => Ok(Some(number - 1)),
+            Ok(number) => Err(format!("There is no {}, select a number between 1 and {}", number, count)),
+            Err(_) => Ok(None),
+        }
+    };
+
+    if let Some(index) = number(item)? {
+        return Ok(Some(index..=index));
+    }
+    if let Some((from, to)) = item.split_once('-') {
+        if let (Some(from), Some(to)) = (number(from)?, number(to)?) {
+            if from > to {
+                return Err(format!("The range {} must start with the lowest number", item));
+            }
+            return Ok(Some(from..=to));
+        }
+    }
+    Ok(None)
+}
+
+/// Menu indexes of the entries an item of the selection refers to
+fn resolve(menu: &[MenuLine], entries: &[usize], item: &str) -> Result<Vec<usize>, String> {
+    if let Some(range) = parse_range(item, entries.len())? {
+        return Ok(range.map(|number| entries[number]).collect());
+    }
+
+    let lowercase = item.to_lowercase();
+    let headers = menu.iter()
+        .enumerate()
+        .filter(|(_, line)| matches!(line, MenuLine::Header(_, text) if text.to_lowercase() == lowercase))
+        .flat_map(|(index, _)| children(menu, index))
+        .collect::<Vec<usize>>();
+    if !headers.is_empty() {
+        return Ok(headers);
+    }
+    let named = entries.iter()
+        .copied()
+        .filter(|index| match &menu[*index] {
+            MenuLine::Entry(_, label, pim) => {
+                label.to_lowercase().contains(&lowercase) || pim.name().to_lowercase().contains(&lowercase)
+            }
+            MenuLine::Header(..) => false,
+        })
+        .collect::<Vec<usize>>();
+    if named.is_empty() {
+        Err(format!("'{}' does not match any role or group", item))
+    } else {
+        Ok(named)
+    }
+}
+
+/// Menu indexes of the entries under the header at the index
+fn children(menu: &[MenuLine], index: usize) -> Vec<usize> {
+    let MenuLine::Header(depth, _) = &menu[index] else {
+        return vec![index];
+    };
+    menu.iter()
+        .enumerate()
+        .skip(index + 1)
+        .take_while(|(_, line)| match line {
+            MenuLine::Header(child_depth, _) | MenuLine::Entry(child_depth, _, _) => child_depth > depth,
+        })
+        .filter(|(_, line)| matches!(line, MenuLine::Entry(..)))
+        .map(|(child, _)| child)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cmd::pim::test_pim::TestPim;
+    use crate::cmd::pim::{MenuLine, Pim};
+
+    use super::{parse_selection, select};
+
+    fn names(selected: Result<Vec<&dyn Pim>, String>) -> Vec<String> {
+        selected.unwrap().iter().map(|pim| pim.name()).collect()
+    }
+
+    fn menu<'a>(pims: &'a [TestPim]) -> Vec<MenuLine<'a>> {
+        vec![
+            MenuLine::Header(0, "Readers".to_owned()),
+            MenuLine::Entry(1, "prod-reader".to_owned(), &pims[0]),
+            MenuLine::Entry(1, "test-reader".to_owned(), &pims[1]),
+            MenuLine::Header(0, "Owners".to_owned()),
+            MenuLine::Entry(1, "prod-aks-owner".to_owned(), &pims[2]),
+            MenuLine::Entry(1, "test-owner".to_owned(), &pims[3]),
+        ]
+    }
+
+    fn pims() -> Vec<TestPim> {
+        ["prod-reader", "test-reader", "prod-aks-owner", "test-owner"].into_iter().map(TestPim::new).collect()
+    }
+
+    #[test]
+    fn selects_numbers_and_ranges() {
+        let pims = pims();
+        let menu = menu(&pims);
+        assert_eq!(names(select(&menu, "1, 4")), ["prod-reader", "test-owner"]);
+        assert_eq!(names(select(&menu, "2-4")), ["test-reader", "prod-aks-owner", "test-owner"]);
+    }
+
+    #[test]
+    fn rejects_reversed_ranges_and_numbers_out_of_range() {
+        let pims = pims();
+        let menu = menu(&pims);
+        assert!(select(&menu, "3-2").is_err());
+        assert!(select(&menu, "5").is_err());
+        assert!(select(&menu, "0").is_err());
+        assert!(select(&menu, "1-5").is_err());
+    }
+
+    #[test]
+    fn selects_every_role_under_a_header() {
+        let pims = pims();
+        let menu = menu(&pims);
+        assert_eq!(names(select(&menu, "owners")), ["prod-aks-owner", "test-owner"]);
+    }
+
+    #[test]
+    fn selects_roles_by_part_of_their_name() {
+        let pims = pims();
+        let menu = menu(&pims);
+        assert_eq!(names(select(&menu, "prod")), ["prod-reader", "prod-aks-owner"]);
+        assert!(select(&menu, "staging").is_err());
+    }
+
+    #[test]
+    fn excludes_roles_prefixed_with_an_exclamation_mark() {
+        let pims = pims();
+        let menu = menu(&pims);
+        assert_eq!(names(select(&menu, "prod,!prod-aks")), ["prod-reader"]);
+        assert_eq!(names(select(&menu, "1-4, !readers")), ["prod-aks-owner", "test-owner"]);
+        assert!(select(&menu, "!1").is_err());
+    }
+
+    #[test]
+    fn selects_roles_in_the_menu_twice_once() {
+        let pims = pims();
+        let mut menu = menu(&pims);
+        menu.insert(0, MenuLine::Entry(1, "prod-reader".to_owned(), &pims[0]));
+        assert_eq!(names(select(&menu, "1,2")), ["prod-reader"]);
+        assert!(select(&menu, "1,!2").unwrap().is_empty());
+    }
+
+    #[test]
+    fn parses_numbers_and_ranges_into_sorted_indexes() {
+        assert_eq!(parse_selection("3, 1-2", 3).unwrap(), [0, 1, 2]);
+        assert_eq!(parse_selection("2-3,3", 3).unwrap(), [1, 2]);
+    }
+
+    #[test]
+    fn rejects_selections_that_are_not_numbers_in_range() {
+        assert_eq!(parse_selection("4", 3).unwrap_err(), "There is no 4, select a number between 1 and 3");
+        assert_eq!(parse_selection("3-1", 3).unwrap_err(), "The range 3-1 must start with the lowest number");
+        assert!(parse_selection("prod", 3).is_err());
+        assert!(parse_selection("", 3).is_err());
+    }
+}
@@ -0,0 +1,40 @@
+use clap::Args;
+
+use crate::cmd::pim::cache::Cache;
+use crate::cmd::pim::config::Config;
+use crate::cmd::pim::{format_local, Clients, Pims};
+
+#[derive(Args)]
+pub struct StatusArgs {
+    #[arg(short, long, help = "Fetch updated cache roles from Azure")]
+    refresh: bool,
+}
+
+pub async fn status(args: &StatusArgs, config: &Config) {
+    let cache = Cache {
+        refresh: args.refresh,
+        ttl: config.cache_ttl(),
+    };
+    let clients = Clients::new(config);
+    let pims = Pims::load(&clients, &cache, config, true).await;
+    pims.report_errors();
+    pims.warn_expiring(config.expiry_warning_days);
+    let mut active = pims.all()
+        .into_iter()
+        .filter_map(|pim| pim.active().map(|active| (pim, active)))
+        .collect::<Vec<_>>();
+
+    if active.is_empty() {
+        pims.no_active_roles("You have no active roles");
+        return;
+    }
+
+    // Roles expiring first are listed first, permanent roles last
+    active.sort_by_key(|(_, active)| (active.end.is_none(), active.end));
+    active.into_iter().for_each(|(pim, active)| {
+        let start = active.start.map(|start| format_local(&start)).unwrap_or_default();
+        let end = active.end.map(|end| format_local(&end)).unwrap_or_default();
+        println!("[{}] {}", pim.source(), pim.name());
+        println!("\t{} - {}, {}", start, end, active.expires_in());
+    });
+}
@@ -0,0 +1,97 @@
+use std::future::Future;
+use std::pin::Pin;
+
+use chrono::{DateTime, Utc};
+
+use crate::azure::Activation;
+use crate::cmd::pim::policy::Policy;
+use crate::cmd::pim::{ActiveAssignment, Pim, Source};
+
+/// A role that is only described, for testing what is done with roles before anything is requested. Requests for it are
+/// never accepted
+pub struct TestPim {
+    pub name: String,
+    pub source: Source,
+    // The header the role is shown under, its name unless set
+    pub group: String,
+    // Its name unless set
+    pub selectors: Vec<String>,
+    pub policy: Option<Policy>,
+    pub eligibility_end: Option<DateTime<Utc>>,
+}
+
+impl TestPim {
+    pub fn new(name: &str) -> TestPim {
+        TestPim {
+            name: name.to_owned(),
+            source: Source::Group,
+            group: name.to_owned(),
+            selectors: vec![name.to_owned()],
+            policy: None,
+            eligibility_end: None,
+        }
+    }
+}
+
+impl<'a> Pim<'a> for TestPim {
+    fn source(&self) -> Source {
+        self.source
+    }
+
+    fn group_by(&self) -> String {
+        self.group.clone()
+    }
+
+    fn resource_id(&self) -> String {
+        self.name.clone()
+    }
+
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn selectors(&self) -> Vec<String> {
+        self.selectors.clone()
+    }
+
+    fn ensure_token(&self) -> Pin<Box<dyn Future<Output=()> + 'a>> {
+        Box::pin(async {})
+    }
+
+    fn activate(&self, _activation: Activation) -> Pin<Box<dyn Future<Output=Option<String>> + 'a>> {
+        Box::pin(async { None })
+    }
+
+    fn request_status(&self, _request_id: String) -> Pin<Box<dyn Future<Output=Result<String, String>> + 'a>> {
+        Box::pin(async { Err("test roles are never requested".to_owned()) })
+    }
+
+    fn active(&self) -> Option<&ActiveAssignment> {
+        None
+    }
+
+    fn deactivate(&self) -> Pin<Box<dyn Future<Output=Option<String>> + 'a>> {
+        Box::pin(async { None })
+    }
+
+    fn policy(&self) -> Pin<Box<dyn Future<Output=Result<Policy, String>> + 'a>> {
+        let policy = self.policy.clone().ok_or_else(|| "no policy".to_owned());
+        Box::pin(async move { policy })
+    }
+
+    fn cached_policy(&self) -> Option<&Policy> {
+        self.policy.as_ref()
+    }
+
+    fn last_reason(&self) -> Pin<Box<dyn Future<Output=Option<String>> + 'a>> {
+        Box::pin(async { None })
+    }
+
+    fn eligibility_end(&self) -> Option<DateTime<Utc>> {
+        self.eligibility_end
+    }
+
+    fn renew(&self, _activation: Activation) -> Pin<Box<dyn Future<Output=Option<String>> + 'a>> {
+        Box::pin(async { None })
+    }
+}
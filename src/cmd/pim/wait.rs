@@ -0,0 +1,45 @@
+use std::time::Duration;
+
+use futures::future::join_all;
+use tokio::time::{sleep, Instant};
+
+use crate::cmd::pim::Pim;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Polls every request until it is provisioned, denied or the timeout is reached.
+/// Returns true if every request was provisioned
+pub async fn wait<'a>(requests: Vec<(&'a dyn Pim<'a>, String)>, timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    join_all(requests.into_iter().map(|(pim, request_id)| wait_for(pim, request_id, deadline)))
+        .await
+        .into_iter()
+        .all(|provisioned| provisioned)
+}
+
+async fn wait_for<'a>(pim: &'a dyn Pim<'a>, request_id: String, deadline: Instant) -> bool {
+    let name = pim.name();
+    let mut last_status = String::new();
+    loop {
+        // Errors reading the status are shown like a status, and polled again as they are often transient
+        let status = pim.request_status(request_id.clone())
+            .await
+            .unwrap_or_else(|error| format!("Failed to read the status of the request: {}", error));
+        if status != last_status {
+            println!("{}: {}", name, status);
+        }
+        match status.as_str() {
+            "Provisioned" | "Granted" | "ScheduleCreated" => return true,
+            "Denied" | "AdminDenied" | "Failed" | "FailedAsResourceIsLocked" | "Canceled" | "Revoked" | "TimedOut"
+            | "Invalid" => return false,
+            _ => {}
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            println!("{}: Timed out waiting for activation", name);
+            return false;
+        }
+        sleep(POLL_INTERVAL.min(deadline - now)).await;
+        last_status = status;
+    }
+}
@@ -1,4 +1,3 @@
-#![feature(slice_group_by)]
 #![feature(future_join)]
 #![feature(async_closure)]
 
@@ -13,6 +12,8 @@ extern crate serde_json;
 extern crate tokio;
 extern crate uuid;
 
+use std::future::Future;
+
 use clap::{Parser, Subcommand};
 
 mod cmd;
@@ -23,6 +24,8 @@ pub mod kubernetes;
 #[command(author, version, about, long_about = None)]
 #[command(propagate_version = true)]
 struct Cli {
+    #[command(flatten)]
+    config: cmd::pim::config::ConfigArgs,
     #[command(subcommand)]
     command: Commands,
 }
@@ -31,6 +34,20 @@ struct Cli {
 enum Commands {
     RefreshAks(cmd::refresh::RefreshAksArgs),
     Pim(cmd::pim::PimArgs),
+    /// Approve or deny requests waiting for your approval
+    Approvals,
+    /// Deactivate active roles before they expire
+    Deactivate(cmd::pim::deactivate::DeactivateArgs),
+    /// Extend active roles before they expire
+    Extend(cmd::pim::extend::ExtendArgs),
+    /// Request extension of eligibilities that are about to end
+    Renew(cmd::pim::renew::RenewArgs),
+    /// Show active roles and when they expire
+    Status(cmd::pim::status::StatusArgs),
+    /// List your recent requests and cancel pending ones
+    Requests(cmd::pim::requests::RequestsArgs),
+    /// Show the configuration in effect, with the settings from the environment and command line applied
+    Config,
 }
 
 fn main() {
@@ -38,13 +55,33 @@ fn main() {
 
     match &cli.command {
         Commands::RefreshAks(args) => cmd::refresh::refresh(args),
-        Commands::Pim(args) => {
-            tokio::runtime::Builder::new_multi_thread()
-                .enable_io()
-                .enable_time()
-                .build()
-                .unwrap()
-                .block_on(cmd::pim::pim(args));
+        Commands::Pim(args) => block_on(cmd::pim::pim(args, &load_config(&cli.config))),
+        Commands::Approvals => block_on(cmd::pim::approvals::approvals(&load_config(&cli.config))),
+        Commands::Deactivate(args) => block_on(cmd::pim::deactivate::deactivate(args, &load_config(&cli.config))),
+        Commands::Extend(args) => block_on(cmd::pim::extend::extend(args, &load_config(&cli.config))),
+        Commands::Renew(args) => block_on(cmd::pim::renew::renew(args, &load_config(&cli.config))),
+        Commands::Status(args) => block_on(cmd::pim::status::status(args, &load_config(&cli.config))),
+        Commands::Requests(args) => block_on(cmd::pim::requests::requests(args, &load_config(&cli.config))),
+        Commands::Config => cmd::pim::config::config(&cli.config),
+    }
+}
+
+/// Loads the configuration for the commands that use it, exiting if it is not valid
+fn load_config(args: &cmd::pim::config::ConfigArgs) -> cmd::pim::config::Config {
+    match cmd::pim::config::Config::load(args) {
+        Ok(config) => config,
+        Err(error) => {
+            eprintln!("{}", error);
+            std::process::exit(1);
         }
     }
 }
+
+fn block_on<F: Future>(future: F) -> F::Output {
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_io()
+        .enable_time()
+        .build()
+        .unwrap()
+        .block_on(future)
+}